@@ -1,32 +1,32 @@
-use persistance::models::Task;
+use persistance::models::{Scan, Task};
 use persistance::queries::admin::MediaDateMap;
+use persistance::queries::duplicates::DuplicateGroups;
+use persistance::queries::similarity::SimilarFileInfo;
 use persistance::{queries, FotoboekDatabase};
-use rocket::serde::{json::Json, Serialize};
+use rocket::response::Debug;
+use rocket::serde::json::Json;
 use rocket::State;
 use shared::models::FotoboekConfig;
 
-#[derive(Serialize)]
-pub struct ScanResponse {
-    images_total: usize,
-    images_added: usize,
-    images_removed: usize,
-    videos_total: usize,
-    videos_added: usize,
-    videos_removed: usize,
+#[post("/admin/scan")]
+pub async fn scan(
+    db: FotoboekDatabase,
+    config: &State<FotoboekConfig>,
+) -> Result<Json<Scan>, Debug<String>> {
+    let scan = logic::scan::start_scan(db, &config, logic::scan::TRIGGER_MANUAL).await?;
+    Ok(Json(scan))
 }
 
-#[post("/admin/scan")]
-pub async fn scan(db: FotoboekDatabase, config: &State<FotoboekConfig>) -> Json<ScanResponse> {
-    let images_result = logic::source_images::search_and_update_db(&db, &config).await;
-    let videos_result = logic::source_videos::search_and_update_db(&db, &config).await;
-    Json(ScanResponse {
-        images_total: images_result.total_count,
-        images_added: images_result.added_count,
-        images_removed: images_result.removed_count,
-        videos_total: videos_result.total_count,
-        videos_added: videos_result.added_count,
-        videos_removed: videos_result.removed_count,
-    })
+#[get("/admin/scans")]
+pub async fn scans(db: FotoboekDatabase) -> Json<Vec<Scan>> {
+    let scans = Scan::all(&db).await;
+    Json(scans)
+}
+
+#[get("/admin/scans/<scan_id>")]
+pub async fn scan_by_id(db: FotoboekDatabase, scan_id: i32) -> Option<Json<Scan>> {
+    let scan = Scan::by_id(&db, scan_id).await?;
+    Some(Json(scan))
 }
 
 #[get("/admin/tasks")]
@@ -35,8 +35,69 @@ pub async fn tasks(db: FotoboekDatabase) -> Json<Vec<Task>> {
     Json(tasks)
 }
 
+#[get("/admin/tasks/failed")]
+pub async fn failed_tasks(db: FotoboekDatabase) -> Json<Vec<Task>> {
+    let tasks = Task::failed(&db).await;
+    Json(tasks)
+}
+
+/// Makes a failed task workable again, with all attempts available.
+#[post("/admin/tasks/<task_id>/retry")]
+pub async fn retry_task(
+    db: FotoboekDatabase,
+    task_id: i32,
+) -> Result<Option<Json<Task>>, Debug<String>> {
+    match failed_task_by_id(&db, task_id).await {
+        Some(task) => {
+            let task = task.retry(&db).await?;
+            logic::worker::notify_tasks_enqueued();
+            Ok(Some(Json(task)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Deletes a failed task, its module will not be run for the file until the file changes. Tasks
+/// depending on it are run anyway.
+#[delete("/admin/tasks/<task_id>")]
+pub async fn discard_task(db: FotoboekDatabase, task_id: i32) -> Result<Option<()>, Debug<String>> {
+    match failed_task_by_id(&db, task_id).await {
+        Some(task) => {
+            task.discard(&db).await?;
+            logic::worker::notify_tasks_enqueued();
+            Ok(Some(()))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Only failed tasks may be retried or discarded, others are about to be run by the workers.
+async fn failed_task_by_id(db: &FotoboekDatabase, task_id: i32) -> Option<Task> {
+    Task::by_id(db, task_id)
+        .await
+        .filter(|task| task.is_failed())
+}
+
 #[get("/admin/media-statistics")]
 pub async fn media_statistics(db: FotoboekDatabase) -> Json<MediaDateMap> {
     let media_date_map = queries::admin::get_media_date_map(&db).await;
     Json(media_date_map)
 }
+
+#[get("/admin/duplicates")]
+pub async fn duplicates(db: FotoboekDatabase) -> Json<DuplicateGroups> {
+    let duplicate_groups = queries::duplicates::groups(&db).await;
+    Json(duplicate_groups)
+}
+
+#[get("/admin/near-duplicates?<max_distance>&<max_seconds_apart>")]
+pub async fn near_duplicates(
+    db: FotoboekDatabase,
+    config: &State<FotoboekConfig>,
+    max_distance: Option<u32>,
+    max_seconds_apart: Option<i64>,
+) -> Json<Vec<Vec<SimilarFileInfo>>> {
+    let max_distance = max_distance.unwrap_or(config.similarity_max_distance);
+    let clusters = queries::similarity::clusters(&db, max_distance, max_seconds_apart).await;
+    Json(clusters)
+}
@@ -0,0 +1,134 @@
+use crate::api::ranged_file::RangedFile;
+use log::warn;
+use persistance::models::{File, FileMetadata};
+use persistance::queries::similarity;
+use persistance::queries::similarity::SimilarFileInfo;
+use persistance::FotoboekDatabase;
+use rocket::http::{Header, Status};
+use rocket::response::status::BadRequest;
+use rocket::serde::json::Json;
+use rocket::State;
+use serde::Serialize;
+use shared::models::FotoboekConfig;
+use shared::path_utils::rel_to_abs_checked;
+use std::collections::HashMap;
+
+/// Limits the number of files of a batch request, SQLite allows at most 999 bound variables.
+const MAX_BATCH_SIZE: usize = 500;
+
+#[derive(Serialize)]
+pub struct FileDetails {
+    #[serde(flatten)]
+    pub file: File,
+    /// Not available until the metadata task of the file finished
+    pub metadata: Option<FileMetadata>,
+}
+
+#[get("/files/<file_id>")]
+pub async fn file_by_id(db: FotoboekDatabase, file_id: i32) -> Option<Json<FileDetails>> {
+    let file = File::by_id(&db, file_id).await.ok()?;
+    let metadata = FileMetadata::by_file_id(&db, file_id).await;
+    Some(Json(FileDetails { file, metadata }))
+}
+
+/// Returns the details of all given files that exist, in the order of the given ids.
+#[get("/files?<ids>")]
+pub async fn files_by_ids(
+    db: FotoboekDatabase,
+    ids: Vec<i32>,
+) -> Result<Json<Vec<FileDetails>>, BadRequest<String>> {
+    if ids.len() > MAX_BATCH_SIZE {
+        return Err(BadRequest(Some(format!(
+            "At most {} ids are allowed per request",
+            MAX_BATCH_SIZE
+        ))));
+    }
+
+    let mut files: HashMap<i32, File> = File::by_ids(&db, ids.clone())
+        .await
+        .into_iter()
+        .map(|file| (file.id.unwrap(), file))
+        .collect();
+    let mut metadata: HashMap<i32, FileMetadata> = FileMetadata::by_file_ids(&db, ids.clone())
+        .await
+        .into_iter()
+        .map(|metadata| (metadata.file_id.unwrap(), metadata))
+        .collect();
+
+    let file_details = ids
+        .iter()
+        .filter_map(|file_id| {
+            files.remove(file_id).map(|file| FileDetails {
+                file,
+                metadata: metadata.remove(file_id),
+            })
+        })
+        .collect();
+    Ok(Json(file_details))
+}
+
+/// Serves the original file as a download.
+#[get("/files/<file_id>/original")]
+pub async fn original(
+    db: FotoboekDatabase,
+    config: &State<FotoboekConfig>,
+    file_id: i32,
+) -> Result<RangedFile, Status> {
+    let file = File::by_id(&db, file_id)
+        .await
+        .map_err(|_| Status::NotFound)?;
+    let abs_path = rel_to_abs_checked(config, &file.rel_path).ok_or_else(|| {
+        warn!(
+            "Refusing to serve missing or outside file {}",
+            file.rel_path
+        );
+        Status::NotFound
+    })?;
+
+    let ranged_file = RangedFile::open(&abs_path)
+        .await
+        .map_err(|_| Status::NotFound)?;
+    Ok(ranged_file.header(content_disposition(&file.file_name)))
+}
+
+/// Creates an attachment header with a plain ASCII file name for old browsers and the percent
+/// encoded UTF-8 file name for all others (RFC 6266).
+fn content_disposition(file_name: &str) -> Header<'static> {
+    let ascii_file_name: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded_file_name: String = file_name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+
+    Header::new(
+        "Content-Disposition",
+        format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            ascii_file_name, encoded_file_name
+        ),
+    )
+}
+
+#[get("/files/<file_id>/similar?<max_distance>")]
+pub async fn similar_files(
+    db: FotoboekDatabase,
+    config: &State<FotoboekConfig>,
+    file_id: i32,
+    max_distance: Option<u32>,
+) -> Json<Vec<SimilarFileInfo>> {
+    let max_distance = max_distance.unwrap_or(config.similarity_max_distance);
+    let similar_files = similarity::similar_files(&db, file_id, max_distance).await;
+    Json(similar_files)
+}
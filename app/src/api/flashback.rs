@@ -3,8 +3,11 @@ use persistance::queries::flashback::FlashbackDates;
 use persistance::FotoboekDatabase;
 use rocket::serde::json::Json;
 
-#[get("/flashback/dates")]
-pub async fn get_dates(db: FotoboekDatabase) -> Json<FlashbackDates> {
-    let dates = flashback::dates(db).await;
+#[get("/flashback/dates?<hide_duplicates>")]
+pub async fn get_dates(
+    db: FotoboekDatabase,
+    hide_duplicates: Option<bool>,
+) -> Json<FlashbackDates> {
+    let dates = flashback::dates(db, hide_duplicates.unwrap_or(false)).await;
     Json(dates)
 }
@@ -3,14 +3,18 @@ use persistance::queries::gallery;
 use persistance::queries::gallery::GalleryFileInfo;
 use persistance::FotoboekDatabase;
 use rocket::serde::json::Json;
+use rocket::State;
 use serde::Serialize;
-use std::collections::HashMap;
-use std::path::Path;
+use shared::models::FotoboekConfig;
+use shared::path_utils::has_extension;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 #[get("/gallery/paths")]
-pub async fn get_paths(db: FotoboekDatabase) -> Json<GalleryPath> {
+pub async fn get_paths(db: FotoboekDatabase, config: &State<FotoboekConfig>) -> Json<GalleryPath> {
     let gallery_file_infos = gallery::get_gallery_file_infos(&db).await;
-    let path_structure = create_gallery_path_structure(gallery_file_infos);
+    let paired_file_infos = pair_raw_files(gallery_file_infos, &config.raw_extensions);
+    let path_structure = create_gallery_path_structure(paired_file_infos);
     Json(path_structure)
 }
 
@@ -34,15 +38,62 @@ pub struct GalleryFile {
     pub id: i32,
     pub file_name: String,
     pub file_type: String,
+    /// Hash of the file contents, the version of its preview and video URLs
+    pub file_hash: String,
     pub effective_date: NaiveDateTime,
+    /// Id of the RAW file shot together with this image, if any
+    pub raw_file_id: Option<i32>,
+    /// Duration of videos in seconds
+    pub video_duration: Option<i32>,
 }
 
-fn create_gallery_path_structure(file_infos: Vec<GalleryFileInfo>) -> GalleryPath {
+/// RAW files with a non-RAW image of the same basename in the same directory, usually the JPEG
+/// the camera wrote alongside, are not listed on their own but attached to that image. If several
+/// images have the basename of a RAW file, it is attached to the first of them only.
+fn pair_raw_files(
+    file_infos: Vec<GalleryFileInfo>,
+    raw_extensions: &[String],
+) -> Vec<(GalleryFileInfo, Option<i32>)> {
+    let is_raw =
+        |file_info: &GalleryFileInfo| has_extension(Path::new(&file_info.rel_path), raw_extensions);
+    let without_extension =
+        |file_info: &GalleryFileInfo| Path::new(&file_info.rel_path).with_extension("");
+
+    let raw_file_ids: HashMap<PathBuf, i32> = file_infos
+        .iter()
+        .filter(|file_info| is_raw(file_info))
+        .map(|file_info| (without_extension(file_info), file_info.file_id))
+        .collect();
+
+    let mut paired_raw_file_ids = HashSet::new();
+    let paired_file_infos: Vec<(GalleryFileInfo, Option<i32>)> = file_infos
+        .into_iter()
+        .map(|file_info| {
+            let raw_file_id = if file_info.file_type == "IMAGE" && !is_raw(&file_info) {
+                raw_file_ids
+                    .get(&without_extension(&file_info))
+                    .copied()
+                    .filter(|raw_file_id| !paired_raw_file_ids.contains(raw_file_id))
+            } else {
+                None
+            };
+            paired_raw_file_ids.extend(raw_file_id);
+            (file_info, raw_file_id)
+        })
+        .collect();
+
+    paired_file_infos
+        .into_iter()
+        .filter(|(file_info, _)| !paired_raw_file_ids.contains(&file_info.file_id))
+        .collect()
+}
+
+fn create_gallery_path_structure(file_infos: Vec<(GalleryFileInfo, Option<i32>)>) -> GalleryPath {
     let empty_path = Path::new("");
 
     let mut gallery_root = GalleryPath::empty();
 
-    file_infos.iter().for_each(|file_info| {
+    file_infos.iter().for_each(|(file_info, raw_file_id)| {
         let path = Path::new(&file_info.rel_path);
         let sub_paths: Vec<_> = path
             .ancestors()
@@ -61,19 +112,93 @@ fn create_gallery_path_structure(file_infos: Vec<GalleryFileInfo>) -> GalleryPat
                         .or_insert(GalleryPath::empty())
                 });
 
-        let gallery_file = create_gallery_file(&file_info);
+        let gallery_file = create_gallery_file(&file_info, *raw_file_id);
         matching_gallery_item.files.push(gallery_file);
     });
 
     gallery_root
 }
 
-fn create_gallery_file(file_info: &GalleryFileInfo) -> GalleryFile {
+fn create_gallery_file(file_info: &GalleryFileInfo, raw_file_id: Option<i32>) -> GalleryFile {
     GalleryFile {
         id: file_info.file_id,
         file_name: file_info.file_name.clone(),
         file_type: file_info.file_type.clone(),
+        file_hash: file_info.file_hash.clone(),
         effective_date: file_info.effective_date.clone(),
+        raw_file_id,
+        video_duration: file_info.video_duration,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_extensions() -> Vec<String> {
+        vec!["cr2".to_string(), "nef".to_string()]
+    }
+
+    fn file_info(file_id: i32, rel_path: &str) -> GalleryFileInfo {
+        GalleryFileInfo {
+            file_id,
+            rel_path: rel_path.to_string(),
+            file_name: Path::new(rel_path)
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+            file_type: "IMAGE".to_string(),
+            file_hash: "".to_string(),
+            effective_date: NaiveDateTime::from_timestamp(0, 0),
+            video_duration: None,
+        }
+    }
+
+    /// Returns the ids of the listed files with the ids of their RAW files.
+    fn paired_ids(file_infos: Vec<GalleryFileInfo>) -> Vec<(i32, Option<i32>)> {
+        pair_raw_files(file_infos, &raw_extensions())
+            .into_iter()
+            .map(|(file_info, raw_file_id)| (file_info.file_id, raw_file_id))
+            .collect()
+    }
+
+    #[test]
+    fn raw_files_are_paired_regardless_of_extension_case() {
+        let file_infos = vec![
+            file_info(1, "2021/IMG_1.CR2"),
+            file_info(2, "2021/IMG_1.jpg"),
+        ];
+        assert_eq!(vec![(2, Some(1))], paired_ids(file_infos));
+    }
+
+    #[test]
+    fn raw_files_without_image_are_listed_on_their_own() {
+        let file_infos = vec![
+            file_info(1, "2021/IMG_1.nef"),
+            file_info(2, "2021/IMG_2.jpg"),
+        ];
+        assert_eq!(vec![(1, None), (2, None)], paired_ids(file_infos));
+    }
+
+    #[test]
+    fn raw_files_are_paired_with_the_first_of_several_images() {
+        let file_infos = vec![
+            file_info(1, "2021/IMG_1.jpg"),
+            file_info(2, "2021/IMG_1.CR2"),
+            file_info(3, "2021/IMG_1.JPEG"),
+        ];
+        assert_eq!(vec![(1, Some(2)), (3, None)], paired_ids(file_infos));
+    }
+
+    #[test]
+    fn raw_files_are_not_paired_across_directories() {
+        let file_infos = vec![
+            file_info(1, "2021/IMG_1.cr2"),
+            file_info(2, "2022/IMG_1.jpg"),
+        ];
+        assert_eq!(vec![(1, None), (2, None)], paired_ids(file_infos));
     }
 }
 
@@ -1,33 +1,53 @@
+use crate::api::ranged_file::{cache_control, RangedFile};
+use log::warn;
+use logic::OnDemandPreviews;
 use persistance::models::FileMetadata;
 use persistance::{fs, FotoboekDatabase};
-use rocket::fs::NamedFile;
+use rocket::http::Header;
 use rocket::State;
-use shared::models::{FotoboekConfig, PreviewSize};
+use shared::models::FotoboekConfig;
 use std::path::Path;
 
-#[derive(Debug, PartialEq, FromFormField)]
-pub enum RestImageSize {
-    Large,
-    Small,
-}
-
-impl RestImageSize {
-    fn to_preview_size(&self) -> PreviewSize {
-        match self {
-            RestImageSize::Large => PreviewSize::Large,
-            RestImageSize::Small => PreviewSize::Small,
-        }
-    }
-}
-
-#[get("/images/<file_id>?<size>")]
+/// Serves the preview of the given size, which must be one of the configured preview sizes.
+/// Previews requested with the file hash as version `v` are cached for good, all others are
+/// revalidated by their ETag, which changes with the file contents and the settings of the preview
+/// size. Missing previews are generated on demand, as soon as the metadata of the file is known.
+#[get("/images/<file_id>?<size>&<v>")]
 pub async fn image_by_id_and_size(
     db: FotoboekDatabase,
     config: &State<FotoboekConfig>,
+    on_demand_previews: &State<OnDemandPreviews>,
     file_id: i32,
-    size: RestImageSize,
-) -> Option<NamedFile> {
+    size: &str,
+    v: Option<&str>,
+) -> Option<RangedFile> {
+    let preview_size = config.preview_size(size)?;
     let metadata = FileMetadata::by_file_id(&db, file_id).await?;
-    let path = fs::file_preview_path(config, &metadata.file_hash, &size.to_preview_size());
-    NamedFile::open(Path::new(&path)).await.ok()
+    if !fs::preview_exists(config, &metadata.file_hash, preview_size) {
+        let result = on_demand_previews
+            .generate(&db, config, &metadata, preview_size)
+            .await;
+        if let Err(err) = result {
+            warn!(
+                "Generating {} preview of file {} on demand failed: {}",
+                preview_size.name, file_id, err
+            );
+        }
+    }
+    let path = fs::file_preview_path(config, &metadata.file_hash, preview_size);
+    let ranged_file = RangedFile::open(Path::new(&path)).await.ok()?;
+    Some(
+        ranged_file
+            .etag(&format!(
+                "{}-{}-{}-{}-{}-{}",
+                metadata.file_hash,
+                preview_size.name,
+                preview_size.fit.to_string(),
+                preview_size.pixels,
+                preview_size.format.extension(),
+                preview_size.quality
+            ))
+            .header(Header::new("Content-Type", preview_size.format.mime_type()))
+            .header(cache_control(&metadata.file_hash, v)),
+    )
 }
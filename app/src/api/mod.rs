@@ -1,17 +1,31 @@
 mod admin;
+mod files;
 mod flashback;
 mod gallery;
 mod images;
+mod ranged_file;
 mod timeline;
 mod videos;
 
 pub fn routes() -> Vec<rocket::Route> {
     routes![
         admin::scan,
+        admin::scans,
+        admin::scan_by_id,
         admin::tasks,
+        admin::failed_tasks,
+        admin::retry_task,
+        admin::discard_task,
         admin::media_statistics,
+        admin::duplicates,
+        admin::near_duplicates,
+        files::file_by_id,
+        files::files_by_ids,
+        files::original,
+        files::similar_files,
         images::image_by_id_and_size,
         videos::video_by_id,
+        videos::video_sprite_by_id,
         timeline::get_dates,
         gallery::get_paths,
         flashback::get_dates,
@@ -0,0 +1,258 @@
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::fs::File;
+use rocket::tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, ReadBuf, Take};
+use std::io::{self, Seek, SeekFrom};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Cache control for files served by file id only. The contents behind such a URL change when the
+/// file is modified or its previews are regenerated, so browsers must revalidate them with the ETag.
+pub const CACHE_CONTROL_REVALIDATE: &str = "no-cache";
+
+/// Cache control for files served by a versioned URL, whose `v` query parameter is the hash of the
+/// file. A modified file gets a new hash and thereby a new URL, so the contents never change.
+pub const CACHE_CONTROL_IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// Returns the `Cache-Control` header of a file with the given hash, requested with the version `v`.
+/// Only URLs whose version is the current hash of the file are cached without revalidation.
+pub fn cache_control(file_hash: &str, version: Option<&str>) -> Header<'static> {
+    if version == Some(file_hash) {
+        Header::new("Cache-Control", CACHE_CONTROL_IMMUTABLE)
+    } else {
+        Header::new("Cache-Control", CACHE_CONTROL_REVALIDATE)
+    }
+}
+
+/// A file response that supports HTTP range requests, which browsers need to seek in videos and
+/// download managers use to resume downloads. Only single ranges are supported, requests for
+/// multiple ranges are answered with the whole file.
+pub struct RangedFile {
+    file: std::fs::File,
+    len: u64,
+    content_type: ContentType,
+    etag: Option<String>,
+    headers: Vec<Header<'static>>,
+}
+
+impl RangedFile {
+    pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<RangedFile> {
+        let file = File::open(path.as_ref()).await?;
+        let len = file.metadata().await?.len();
+        let content_type = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(ContentType::from_extension)
+            .unwrap_or(ContentType::Binary);
+
+        Ok(RangedFile {
+            file: file.into_std().await,
+            len,
+            content_type,
+            etag: None,
+            headers: vec![],
+        })
+    }
+
+    /// Sets a strong ETag made of the given content id and the file length. Requests whose
+    /// `If-None-Match` header matches the ETag are answered with `304 Not Modified`.
+    pub fn etag(mut self, content_id: &str) -> RangedFile {
+        self.etag = Some(format!("\"{}-{}\"", content_id, self.len));
+        self
+    }
+
+    /// Adds the header to the response, replacing any header of the same name.
+    pub fn header(mut self, header: Header<'static>) -> RangedFile {
+        self.headers.push(header);
+        self
+    }
+}
+
+impl<'r> Responder<'r, 'static> for RangedFile {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        if let Some(etag) = &self.etag {
+            let if_none_match = request.headers().get("If-None-Match");
+            if if_none_match
+                .into_iter()
+                .any(|value| etag_matches(value, etag))
+            {
+                let mut response = Response::build();
+                response
+                    .status(Status::NotModified)
+                    .raw_header("ETag", etag.clone());
+                for header in self.headers {
+                    response.header(header);
+                }
+                return response.ok();
+            }
+        }
+
+        let range = match request.headers().get_one("Range") {
+            Some(value) => parse_range(value, self.len),
+            None => Ok(None),
+        };
+
+        let mut response = Response::build();
+        response
+            .header(self.content_type)
+            .raw_header("Accept-Ranges", "bytes");
+        if let Some(etag) = self.etag {
+            response.raw_header("ETag", etag);
+        }
+        for header in self.headers {
+            response.header(header);
+        }
+
+        let (start, end) = match range {
+            Ok(Some((start, end))) => {
+                response.status(Status::PartialContent).raw_header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", start, end - 1, self.len),
+                );
+                (start, end)
+            }
+            Ok(None) => (0, self.len),
+            Err(()) => {
+                return response
+                    .status(Status::RangeNotSatisfiable)
+                    .raw_header("Content-Range", format!("bytes */{}", self.len))
+                    .ok();
+            }
+        };
+
+        let mut file = self.file;
+        file.seek(SeekFrom::Start(start))
+            .map_err(|_| Status::InternalServerError)?;
+        let body = FileRange {
+            inner: File::from_std(file).take(end - start),
+        };
+        response.sized_body((end - start) as usize, body).ok()
+    }
+}
+
+/// Returns true if the value of an `If-None-Match` header, a list of ETags or `*`, contains the
+/// ETag. The comparison is weak as required for `If-None-Match`, so the `W/` prefix is ignored.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|value| value.trim())
+        .any(|value| value == "*" || value.strip_prefix("W/").unwrap_or(value) == etag)
+}
+
+/// Parses the value of a `Range` header into the start and exclusive end of the requested bytes.
+/// Returns `Ok(None)` if the range is malformed or spans multiple ranges, in which case the header
+/// is ignored, and `Err` if the range can not be satisfied.
+fn parse_range(value: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let range = match value.trim().strip_prefix("bytes=") {
+        Some(range) if !range.contains(',') => range.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match range.split_once('-') {
+        Some(start_and_end) => start_and_end,
+        None => return Ok(None),
+    };
+
+    let (start, end) = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=100-199
+        (Ok(start), Ok(end)) if start <= end => (start, end.saturating_add(1).min(len)),
+        // bytes=100-
+        (Ok(start), Err(_)) if end.is_empty() => (start, len),
+        // bytes=-100, the last 100 bytes
+        (Err(_), Ok(suffix_len)) if start.is_empty() && suffix_len > 0 => {
+            (len.saturating_sub(suffix_len), len)
+        }
+        _ => return Ok(None),
+    };
+
+    if start >= len {
+        Err(())
+    } else {
+        Ok(Some((start, end)))
+    }
+}
+
+/// The requested part of a file. The size of the body is preset, so Rocket never needs to seek.
+struct FileRange {
+    inner: Take<File>,
+}
+
+impl AsyncRead for FileRange {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncSeek for FileRange {
+    fn start_seek(self: Pin<&mut Self>, _position: SeekFrom) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Seeking in a file range is not supported",
+        ))
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Seeking in a file range is not supported",
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn etags_are_matched() {
+        let etag = "\"abc-1000\"";
+        assert!(etag_matches("\"abc-1000\"", etag));
+        assert!(etag_matches("W/\"abc-1000\"", etag));
+        assert!(etag_matches("\"xyz-1\", \"abc-1000\"", etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches("\"abc-999\"", etag));
+        assert!(!etag_matches("abc-1000", etag));
+    }
+
+    #[test]
+    fn only_current_versions_are_immutable() {
+        let immutable = cache_control("abc", Some("abc"));
+        assert_eq!(CACHE_CONTROL_IMMUTABLE, immutable.value());
+        let outdated = cache_control("abc", Some("xyz"));
+        assert_eq!(CACHE_CONTROL_REVALIDATE, outdated.value());
+        let unversioned = cache_control("abc", None);
+        assert_eq!(CACHE_CONTROL_REVALIDATE, unversioned.value());
+    }
+
+    #[test]
+    fn ranges_are_parsed() {
+        assert_eq!(Ok(Some((0, 100))), parse_range("bytes=0-99", 1000));
+        assert_eq!(Ok(Some((500, 1000))), parse_range("bytes=500-", 1000));
+        assert_eq!(Ok(Some((900, 1000))), parse_range("bytes=-100", 1000));
+        assert_eq!(Ok(Some((0, 1000))), parse_range("bytes=-2000", 1000));
+        assert_eq!(Ok(Some((990, 1000))), parse_range("bytes=990-1999", 1000));
+    }
+
+    #[test]
+    fn invalid_ranges_are_ignored() {
+        assert_eq!(Ok(None), parse_range("bytes=0-99,200-299", 1000));
+        assert_eq!(Ok(None), parse_range("items=0-99", 1000));
+        assert_eq!(Ok(None), parse_range("bytes=99-0", 1000));
+        assert_eq!(Ok(None), parse_range("bytes=-", 1000));
+        assert_eq!(Ok(None), parse_range("bytes=-0", 1000));
+        assert_eq!(Ok(None), parse_range("bytes=abc", 1000));
+    }
+
+    #[test]
+    fn ranges_beyond_the_end_are_not_satisfiable() {
+        assert_eq!(Err(()), parse_range("bytes=1000-", 1000));
+        assert_eq!(Err(()), parse_range("bytes=1000-1999", 1000));
+        assert_eq!(Err(()), parse_range("bytes=0-", 0));
+    }
+}
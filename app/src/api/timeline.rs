@@ -17,8 +17,8 @@ pub struct GetByDateQueryParams {
     pub limit: usize,
 }
 
-#[get("/timeline/dates")]
-pub async fn get_dates(db: FotoboekDatabase) -> Json<TimelineDates> {
-    let dates = timeline::dates(db).await;
+#[get("/timeline/dates?<hide_duplicates>")]
+pub async fn get_dates(db: FotoboekDatabase, hide_duplicates: Option<bool>) -> Json<TimelineDates> {
+    let dates = timeline::dates(db, hide_duplicates.unwrap_or(false)).await;
     Json(dates)
 }
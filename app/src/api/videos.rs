@@ -1,17 +1,110 @@
-use persistance::models::FileMetadata;
+use crate::api::ranged_file::{cache_control, RangedFile, CACHE_CONTROL_REVALIDATE};
+use log::warn;
+use persistance::models::{File, FileMetadata, Task};
 use persistance::{fs, FotoboekDatabase};
-use rocket::fs::NamedFile;
+use rocket::http::Header;
 use rocket::State;
 use shared::models::FotoboekConfig;
+use shared::path_utils::rel_to_abs_checked;
 use std::path::Path;
 
-#[get("/videos/<file_id>")]
+const TRANSCODE_STATUS_HEADER: &str = "X-Transcode-Status";
+
+#[derive(Debug)]
+enum TranscodeStatus {
+    /// The transcoded video is served.
+    Done,
+    /// A worker is transcoding the video right now.
+    Running,
+    /// The transcode task waits for a worker, or for a retry after it failed.
+    Pending,
+    /// The transcode task failed too often and is not retried anymore.
+    Failed,
+    /// There is neither a transcoded video nor a task to create it.
+    Unavailable,
+}
+
+impl TranscodeStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TranscodeStatus::Done => "done",
+            TranscodeStatus::Running => "running",
+            TranscodeStatus::Pending => "pending",
+            TranscodeStatus::Failed => "failed",
+            TranscodeStatus::Unavailable => "unavailable",
+        }
+    }
+
+    fn header(&self) -> Header<'static> {
+        Header::new(TRANSCODE_STATUS_HEADER, self.as_str())
+    }
+}
+
+/// Serves the transcoded video, or the original file as long as the transcode is not finished. The
+/// `X-Transcode-Status` header tells which of both was served, their ETags differ so browsers
+/// revalidate to the transcoded video once it exists. Only the transcoded video is cached for good
+/// when requested with the file hash as version `v`, the original is always revalidated.
+#[get("/videos/<file_id>?<v>")]
 pub async fn video_by_id(
     db: FotoboekDatabase,
     config: &State<FotoboekConfig>,
     file_id: i32,
-) -> Option<NamedFile> {
+    v: Option<&str>,
+) -> Option<RangedFile> {
     let metadata = FileMetadata::by_file_id(&db, file_id).await?;
     let path = fs::video_path(config, &metadata.file_hash);
-    NamedFile::open(Path::new(&path)).await.ok()
+    if let Ok(ranged_file) = RangedFile::open(Path::new(&path)).await {
+        return Some(
+            ranged_file
+                .etag(&format!("{}-video", metadata.file_hash))
+                .header(cache_control(&metadata.file_hash, v))
+                .header(TranscodeStatus::Done.header()),
+        );
+    }
+
+    let status = match Task::by_file_id_and_module(&db, file_id, logic::TRANSCODE_MODULE_ID).await {
+        Some(task) if task.is_failed() => TranscodeStatus::Failed,
+        Some(task) if task.is_locked(config) => TranscodeStatus::Running,
+        Some(_) => TranscodeStatus::Pending,
+        None => TranscodeStatus::Unavailable,
+    };
+    let file = File::by_id(&db, file_id).await.ok()?;
+    let abs_path = rel_to_abs_checked(config, &file.rel_path).or_else(|| {
+        warn!(
+            "Refusing to serve missing or outside file {}",
+            file.rel_path
+        );
+        None
+    })?;
+    let ranged_file = RangedFile::open(&abs_path).await.ok()?;
+    Some(
+        ranged_file
+            .etag(&format!("{}-original", metadata.file_hash))
+            .header(Header::new("Cache-Control", CACHE_CONTROL_REVALIDATE))
+            .header(status.header()),
+    )
+}
+
+/// Serves the sprite sheet of the video: `X-Sprite-Frames` square frames side by side, evenly
+/// distributed over the video. Like previews, it is cached for good if requested with the file hash
+/// as version `v`.
+#[get("/videos/<file_id>/sprite?<v>")]
+pub async fn video_sprite_by_id(
+    db: FotoboekDatabase,
+    config: &State<FotoboekConfig>,
+    file_id: i32,
+    v: Option<&str>,
+) -> Option<RangedFile> {
+    let metadata = FileMetadata::by_file_id(&db, file_id).await?;
+    let path = fs::video_sprite_path(config, &metadata.file_hash);
+    let ranged_file = RangedFile::open(Path::new(&path)).await.ok()?;
+    Some(
+        ranged_file
+            .etag(&format!("{}-sprite", metadata.file_hash))
+            .header(cache_control(&metadata.file_hash, v))
+            .header(Header::new(
+                "X-Sprite-Frames",
+                fs::VIDEO_SPRITE_FRAMES.to_string(),
+            )),
+    )
 }
@@ -1,12 +1,27 @@
-use shared::models::FotoboekConfig;
+use log::warn;
+use shared::models::{
+    FotoboekConfig, PreviewSize, WorkerPool, PREVIEW_SIZE_LARGE, PREVIEW_SIZE_SMALL,
+};
 
 pub fn parse() -> FotoboekConfig {
     FotoboekConfig {
         media_source_path: get_string_env_value("MEDIA_SOURCE_PATH"),
         file_storage_path: get_string_env_value("FILE_STORAGE_PATH"),
         webapp_files_path: get_string_env_value("WEBAPP_FILES_PATH"),
-        num_worker_threads: get_usize_env_value("NUM_WORKER_THREADS"),
+        worker_pools: get_worker_pools_env_value("WORKER_POOLS"),
+        worker_idle_interval_sec: get_usize_env_value("WORKER_IDLE_INTERVAL_SEC"),
         task_lock_timeout_sec: get_usize_env_value("TASK_LOCK_TIMEOUT_SEC"),
+        task_max_attempts: get_usize_env_value("TASK_MAX_ATTEMPTS"),
+        task_retry_backoff_sec: get_usize_env_value("TASK_RETRY_BACKOFF_SEC"),
+        fs_watcher_enabled: get_bool_env_value("FS_WATCHER_ENABLED"),
+        scan_on_startup: get_bool_env_value("SCAN_ON_STARTUP"),
+        scan_interval_hours: get_usize_env_value("SCAN_INTERVAL_HOURS"),
+        similarity_max_distance: get_usize_env_value("SIMILARITY_MAX_DISTANCE") as u32,
+        image_extensions: get_list_env_value("IMAGE_EXTENSIONS"),
+        raw_extensions: get_list_env_value("RAW_EXTENSIONS"),
+        video_extensions: get_list_env_value("VIDEO_EXTENSIONS"),
+        preview_sizes: get_preview_sizes_env_value("PREVIEW_SIZES"),
+        preview_on_demand_max_concurrent: get_usize_env_value("PREVIEW_ON_DEMAND_MAX_CONCURRENT"),
     }
 }
 
@@ -23,3 +38,128 @@ fn get_usize_env_value(name: &str) -> usize {
         .parse()
         .expect(format!("Environment \"{}\" property has invalid value", name).as_str())
 }
+
+fn get_bool_env_value(name: &str) -> bool {
+    dotenv::var(name)
+        .expect(format!("Environment \"{}\" property not found", name).as_str())
+        .parse()
+        .expect(format!("Environment \"{}\" property has invalid value", name).as_str())
+}
+
+fn get_list_env_value(name: &str) -> Vec<String> {
+    get_string_env_value(name)
+        .split(',')
+        .map(|value| value.trim().to_lowercase())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn get_preview_sizes_env_value(name: &str) -> Vec<PreviewSize> {
+    let preview_sizes: Vec<PreviewSize> = get_list_env_value(name)
+        .iter()
+        .map(|value| {
+            value.parse().unwrap_or_else(|err| {
+                panic!(
+                    "Environment \"{}\" property has invalid value: {}",
+                    name, err
+                )
+            })
+        })
+        .collect();
+
+    for required_name in [PREVIEW_SIZE_SMALL, PREVIEW_SIZE_LARGE].iter() {
+        if !preview_sizes.iter().any(|size| &size.name == required_name) {
+            panic!(
+                "Environment \"{}\" property must contain the \"{}\" preview size",
+                name, required_name
+            );
+        }
+    }
+    for (index, size) in preview_sizes.iter().enumerate() {
+        if preview_sizes[..index]
+            .iter()
+            .any(|other| other.name == size.name)
+        {
+            panic!(
+                "Environment \"{}\" property contains the \"{}\" preview size twice",
+                name, size.name
+            );
+        }
+    }
+
+    preview_sizes
+}
+
+/// Falls back to the former `NUM_WORKER_THREADS` property if no pools are configured: All workers
+/// run all modules but the transcode, which has a single worker as before.
+fn get_worker_pools_env_value(name: &str) -> Vec<WorkerPool> {
+    let values = if dotenv::var(name).is_err() && dotenv::var("NUM_WORKER_THREADS").is_ok() {
+        warn!(
+            "Environment \"NUM_WORKER_THREADS\" property is deprecated, use \"{}\" instead",
+            name
+        );
+        let other_modules: Vec<&str> = logic::MODULE_IDS
+            .iter()
+            .copied()
+            .filter(|module| *module != logic::TRANSCODE_MODULE_ID)
+            .collect();
+        vec![
+            format!(
+                "default:{}:{}",
+                get_usize_env_value("NUM_WORKER_THREADS"),
+                other_modules.join("+")
+            ),
+            format!("transcode:1:{}", logic::TRANSCODE_MODULE_ID),
+        ]
+    } else {
+        get_list_env_value(name)
+    };
+
+    let worker_pools: Vec<WorkerPool> = values
+        .iter()
+        .map(|value| {
+            value.parse().unwrap_or_else(|err| {
+                panic!(
+                    "Environment \"{}\" property has invalid value: {}",
+                    name, err
+                )
+            })
+        })
+        .collect();
+
+    for (index, pool) in worker_pools.iter().enumerate() {
+        if worker_pools[..index]
+            .iter()
+            .any(|other| other.name == pool.name)
+        {
+            panic!(
+                "Environment \"{}\" property contains the \"{}\" worker pool twice",
+                name, pool.name
+            );
+        }
+    }
+    for module in worker_pools.iter().flat_map(|pool| pool.modules.iter()) {
+        if !logic::MODULE_IDS.contains(&module.as_str()) {
+            panic!(
+                "Environment \"{}\" property contains the unknown module \"{}\"",
+                name, module
+            );
+        }
+    }
+    // Tasks of a module without a pool would never run, those of a module in several pools would
+    // not be capped by either
+    for module in logic::MODULE_IDS.iter() {
+        let pools_count = worker_pools
+            .iter()
+            .filter(|pool| pool.modules.iter().any(|it| it == module))
+            .count();
+        if pools_count != 1 {
+            panic!(
+                "Environment \"{}\" property must contain the \"{}\" module in exactly one pool",
+                name, module
+            );
+        }
+    }
+
+    worker_pools
+}
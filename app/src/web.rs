@@ -1,5 +1,7 @@
+use log::{error, info};
 use rocket::fairing::AdHoc;
 use rocket::fs::FileServer;
+use rocket::{Build, Rocket};
 use std::path::Path;
 
 use crate::api;
@@ -13,8 +15,16 @@ pub async fn init(config: &FotoboekConfig) {
             "Database Migrations",
             persistance::migration_fairing,
         ))
+        .attach(AdHoc::on_ignite(
+            "Interrupted Scans",
+            interrupted_scans_fairing,
+        ))
         .attach(worker_thread_fairing(config))
+        .attach(scan_scheduler_fairing(config))
+        .attach(fs_watcher_fairing(config))
+        .attach(missing_previews_fairing(config))
         .manage(config.clone())
+        .manage(logic::OnDemandPreviews::new(config))
         .mount("/api", api::routes())
         .mount("/", webapp_route(config))
         .launch()
@@ -28,14 +38,67 @@ fn webapp_route(config: &FotoboekConfig) -> FileServer {
 }
 
 fn worker_thread_fairing(config: &FotoboekConfig) -> AdHoc {
-    let num_worker_threads = config.num_worker_threads;
     let config_copy = config.clone();
     AdHoc::on_liftoff("worker_thread", move |rocket| {
         Box::pin(async move {
-            for i in 0..num_worker_threads {
+            for pool in config_copy.worker_pools.iter() {
+                for i in 0..pool.workers {
+                    let db = FotoboekDatabase::get_one(rocket).await.unwrap();
+                    logic::worker::spawn(db, &config_copy, pool, i);
+                }
+            }
+            let db = FotoboekDatabase::get_one(rocket).await.unwrap();
+            logic::worker::spawn_heartbeat(db, &config_copy);
+        })
+    })
+}
+
+/// Scans that were running when the application stopped never finish, they are marked as failed.
+async fn interrupted_scans_fairing(rocket: Rocket<Build>) -> Rocket<Build> {
+    let db = FotoboekDatabase::get_one(&rocket).await.unwrap();
+    match logic::scan::fail_interrupted_scans(&db).await {
+        Ok(0) => (),
+        Ok(count) => info!("Marked {} interrupted scans as failed", count),
+        Err(err) => error!("Marking interrupted scans as failed failed: {}", err),
+    }
+    rocket
+}
+
+fn scan_scheduler_fairing(config: &FotoboekConfig) -> AdHoc {
+    let config_copy = config.clone();
+    AdHoc::on_liftoff("scan_scheduler", move |rocket| {
+        Box::pin(async move {
+            let db = FotoboekDatabase::get_one(rocket).await.unwrap();
+            logic::scan::spawn_scheduler(db, &config_copy);
+        })
+    })
+}
+
+fn fs_watcher_fairing(config: &FotoboekConfig) -> AdHoc {
+    let config_copy = config.clone();
+    AdHoc::on_liftoff("fs_watcher", move |rocket| {
+        Box::pin(async move {
+            if config_copy.fs_watcher_enabled {
                 let db = FotoboekDatabase::get_one(rocket).await.unwrap();
-                logic::worker::spawn(db, &config_copy, i);
+                logic::watcher::spawn(db, &config_copy);
             }
         })
     })
 }
+
+/// Previews of newly configured sizes are generated for all existing files.
+fn missing_previews_fairing(config: &FotoboekConfig) -> AdHoc {
+    let config_copy = config.clone();
+    AdHoc::on_liftoff("missing_previews", move |rocket| {
+        Box::pin(async move {
+            let db = FotoboekDatabase::get_one(rocket).await.unwrap();
+            rocket::tokio::spawn(async move {
+                match logic::create_tasks_on_missing_previews(&db, &config_copy).await {
+                    Ok(0) => (),
+                    Ok(count) => info!("Created preview tasks for {} files", count),
+                    Err(err) => error!("Creating tasks for missing previews failed: {}", err),
+                }
+            });
+        })
+    })
+}
@@ -1,4 +1,11 @@
 mod modules;
+pub mod scan;
+pub mod source_files;
 pub mod source_images;
 pub mod source_videos;
+pub mod watcher;
 pub mod worker;
+
+pub use modules::{
+    create_tasks_on_missing_previews, OnDemandPreviews, MODULE_IDS, TRANSCODE_MODULE_ID,
+};
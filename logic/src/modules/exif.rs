@@ -0,0 +1,119 @@
+const JPEG_MAGIC: &[u8] = &[0xFF, 0xD8];
+const TIFF_MAGIC_LE: &[u8] = b"II*\0";
+const TIFF_MAGIC_BE: &[u8] = b"MM\0*";
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// Returns the part of the file contents that can be parsed by rexif, which only understands JPEG
+/// and TIFF. For PNG, WebP and HEIC/HEIF files the embedded TIFF structure holding the EXIF data is
+/// located and returned.
+pub fn find_exif(contents: &[u8]) -> Option<&[u8]> {
+    if is_tiff(contents) || contents.starts_with(JPEG_MAGIC) {
+        Some(contents)
+    } else if contents.starts_with(PNG_MAGIC) {
+        find_png_exif(contents)
+    } else if contents.starts_with(b"RIFF") && contents.get(8..12) == Some(b"WEBP") {
+        find_webp_exif(contents)
+    } else {
+        search_exif_header(contents)
+    }
+}
+
+fn is_tiff(contents: &[u8]) -> bool {
+    contents.starts_with(TIFF_MAGIC_LE) || contents.starts_with(TIFF_MAGIC_BE)
+}
+
+fn strip_exif_header(data: &[u8]) -> &[u8] {
+    data.strip_prefix(EXIF_HEADER).unwrap_or(data)
+}
+
+/// PNG stores the EXIF data in an `eXIf` chunk: 4 bytes big endian length, 4 bytes type, data
+/// and 4 bytes CRC.
+fn find_png_exif(contents: &[u8]) -> Option<&[u8]> {
+    let mut offset = PNG_MAGIC.len();
+    while let Some(header) = contents.get(offset..offset + 8) {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let data = contents.get(offset + 8..offset + 8 + length)?;
+        match &header[4..8] {
+            b"eXIf" => return Some(strip_exif_header(data)).filter(|data| is_tiff(data)),
+            b"IDAT" | b"IEND" => return None,
+            _ => offset += 8 + length + 4,
+        }
+    }
+    None
+}
+
+/// WebP stores the EXIF data in an `EXIF` chunk of the RIFF container: 4 bytes type, 4 bytes
+/// little endian length and data padded to an even length.
+fn find_webp_exif(contents: &[u8]) -> Option<&[u8]> {
+    let mut offset = 12;
+    while let Some(header) = contents.get(offset..offset + 8) {
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let data = contents.get(offset + 8..offset + 8 + length)?;
+        if &header[0..4] == b"EXIF" {
+            return Some(strip_exif_header(data)).filter(|data| is_tiff(data));
+        }
+        offset += 8 + length + length % 2;
+    }
+    None
+}
+
+/// HEIC/HEIF store the EXIF data as an item of the ISO base media file, prefixed with the usual
+/// `Exif\0\0` header. Instead of parsing the boxes, the header followed by a TIFF header is searched.
+fn search_exif_header(contents: &[u8]) -> Option<&[u8]> {
+    contents
+        .windows(EXIF_HEADER.len() + TIFF_MAGIC_LE.len())
+        .position(|window| window.starts_with(EXIF_HEADER) && is_tiff(&window[EXIF_HEADER.len()..]))
+        .map(|position| &contents[position + EXIF_HEADER.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rexif::ExifTag;
+
+    fn camera_model(contents: &[u8]) -> Option<String> {
+        let exif = rexif::parse_buffer_quiet(find_exif(contents)?).0.ok()?;
+        exif.entries
+            .iter()
+            .find(|entry| entry.tag == ExifTag::Model)
+            .map(|entry| entry.value_more_readable.to_string())
+    }
+
+    #[test]
+    fn exif_found_in_jpeg() {
+        let contents = include_bytes!("../../tests/fixtures/exif.jpg");
+        assert_eq!(Some("Fotoboek Camera".to_string()), camera_model(contents));
+    }
+
+    #[test]
+    fn exif_found_in_tiff() {
+        let contents = include_bytes!("../../tests/fixtures/exif.tif");
+        assert_eq!(Some("Fotoboek Camera".to_string()), camera_model(contents));
+    }
+
+    #[test]
+    fn exif_found_in_png() {
+        let contents = include_bytes!("../../tests/fixtures/exif.png");
+        assert_eq!(Some("Fotoboek Camera".to_string()), camera_model(contents));
+    }
+
+    #[test]
+    fn exif_found_in_webp() {
+        let contents = include_bytes!("../../tests/fixtures/exif.webp");
+        assert_eq!(Some("Fotoboek Camera".to_string()), camera_model(contents));
+    }
+
+    #[test]
+    fn exif_found_in_heic() {
+        let contents = include_bytes!("../../tests/fixtures/exif.heic");
+        assert_eq!(Some("Fotoboek Camera".to_string()), camera_model(contents));
+    }
+
+    #[test]
+    fn no_exif_in_gif() {
+        let contents = include_bytes!("../../tests/fixtures/no_exif.gif");
+        assert_eq!(None, find_exif(contents));
+    }
+}
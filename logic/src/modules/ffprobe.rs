@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::process::Command;
+
+use chrono::{DateTime, NaiveDateTime};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+
+const LOCATION_TAGS: [&str; 2] = ["location", "com.apple.quicktime.location.iso6709"];
+
+/// Container and stream information as printed by `ffprobe -show_format -show_streams`.
+#[derive(Deserialize, Debug, Default)]
+pub struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<Stream>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Deserialize, Debug)]
+struct Stream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<i32>,
+    height: Option<i32>,
+    avg_frame_rate: Option<String>,
+    r_frame_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SideData {
+    rotation: Option<i32>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Format {
+    duration: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Runs ffprobe on the given file, which works for all containers supported by ffmpeg.
+pub fn probe(abs_path: &str) -> Result<FfprobeOutput, String> {
+    let output = Command::new("ffprobe")
+        .args(&[
+            "-v",
+            "error",
+            "-print_format",
+            "json",
+            "-show_format",
+            "-show_streams",
+        ])
+        .arg(abs_path)
+        .output()
+        .map_err(|err| format!("Failed to run ffprobe: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffprobe failed for {}: {}",
+            abs_path,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    parse(&String::from_utf8_lossy(&output.stdout))
+}
+
+fn parse(json: &str) -> Result<FfprobeOutput, String> {
+    serde_json::from_str(json).map_err(|err| format!("Invalid ffprobe output: {}", err))
+}
+
+impl FfprobeOutput {
+    fn stream(&self, codec_type: &str) -> Option<&Stream> {
+        self.streams
+            .iter()
+            .find(|stream| stream.codec_type.as_deref() == Some(codec_type))
+    }
+
+    fn video_stream(&self) -> Option<&Stream> {
+        self.stream("video")
+    }
+
+    /// Looks up a tag of the container, falling back to the tags of the streams. Tag names differ
+    /// in case between containers, so they are compared case insensitive.
+    fn tag(&self, name: &str) -> Option<&String> {
+        std::iter::once(&self.format.tags)
+            .chain(self.streams.iter().map(|stream| &stream.tags))
+            .flat_map(|tags| tags.iter())
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn resolution(&self) -> Option<(i32, i32)> {
+        self.video_stream()
+            .and_then(|stream| stream.width.zip(stream.height))
+    }
+
+    pub fn creation_date(&self) -> Option<NaiveDateTime> {
+        self.tag("creation_time")
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|date_time| date_time.naive_utc())
+    }
+
+    pub fn duration(&self) -> Option<i32> {
+        self.format
+            .duration
+            .as_ref()
+            .and_then(|value| value.parse::<f64>().ok())
+            .map(|duration| duration as i32)
+    }
+
+    pub fn video_codec(&self) -> Option<String> {
+        self.video_stream()
+            .and_then(|stream| stream.codec_name.clone())
+    }
+
+    pub fn audio_codec(&self) -> Option<String> {
+        self.stream("audio")
+            .and_then(|stream| stream.codec_name.clone())
+    }
+
+    /// Returns the average frame rate, falling back to the base frame rate of the video stream.
+    pub fn frame_rate(&self) -> Option<f32> {
+        self.video_stream().and_then(|stream| {
+            stream
+                .avg_frame_rate
+                .as_deref()
+                .and_then(parse_frame_rate)
+                .or_else(|| stream.r_frame_rate.as_deref().and_then(parse_frame_rate))
+        })
+    }
+
+    /// Returns the clockwise rotation in degrees, either from the `rotate` tag of older ffprobe
+    /// versions or from the display matrix, whose rotation is counterclockwise.
+    pub fn rotation(&self) -> Option<i32> {
+        let stream = self.video_stream()?;
+        let rotation = match stream.tags.get("rotate") {
+            Some(value) => value.parse::<i32>().ok()?,
+            None => -stream
+                .side_data_list
+                .iter()
+                .find_map(|side_data| side_data.rotation)?,
+        };
+        Some(rotation.rem_euclid(360))
+    }
+
+    pub fn gps_lat_lon(&self) -> Option<(f32, f32)> {
+        LOCATION_TAGS
+            .iter()
+            .find_map(|name| self.tag(name))
+            .and_then(|value| parse_iso6709(value))
+    }
+}
+
+/// Parses frame rates like `30000/1001`, ffprobe reports `0/0` if the frame rate is unknown.
+fn parse_frame_rate(value: &str) -> Option<f32> {
+    let (numerator, denominator) = value.split_once('/')?;
+    let numerator: f32 = numerator.parse().ok()?;
+    let denominator: f32 = denominator.parse().ok()?;
+    if numerator > 0. && denominator > 0. {
+        Some(numerator / denominator)
+    } else {
+        None
+    }
+}
+
+/// Parses the latitude and longitude of an ISO 6709 location like `+52.5200+013.4050+034.000/`.
+fn parse_iso6709(value: &str) -> Option<(f32, f32)> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^([+-]\d+(?:\.\d+)?)([+-]\d+(?:\.\d+)?)").unwrap();
+    }
+
+    RE.captures(value).and_then(|cap| {
+        let lat = cap.get(1)?.as_str().parse().ok()?;
+        let lon = cap.get(2)?.as_str().parse().ok()?;
+        Some((lat, lon))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    #[test]
+    fn metadata_of_phone_video() {
+        let output = parse(include_str!("../../tests/fixtures/ffprobe_mov.json")).unwrap();
+        assert_eq!(Some((1920, 1080)), output.resolution());
+        assert_eq!(
+            Some(NaiveDateTime::from_str("2021-07-14T16:20:05").unwrap()),
+            output.creation_date()
+        );
+        assert_eq!(Some(12), output.duration());
+        assert_eq!(Some((52.52, 13.405)), output.gps_lat_lon());
+        assert_eq!(Some("hevc".to_string()), output.video_codec());
+        assert_eq!(Some("aac".to_string()), output.audio_codec());
+        assert_eq!(Some(30.), output.frame_rate());
+        assert_eq!(Some(90), output.rotation());
+    }
+
+    #[test]
+    fn metadata_of_camcorder_video_without_tags() {
+        let output = parse(include_str!("../../tests/fixtures/ffprobe_mts.json")).unwrap();
+        assert_eq!(Some((1440, 1080)), output.resolution());
+        assert_eq!(None, output.creation_date());
+        assert_eq!(Some(63), output.duration());
+        assert_eq!(None, output.gps_lat_lon());
+        assert_eq!(Some("h264".to_string()), output.video_codec());
+        assert_eq!(Some("ac3".to_string()), output.audio_codec());
+        assert_eq!(Some(25.), output.frame_rate());
+        assert_eq!(None, output.rotation());
+    }
+
+    #[test]
+    fn rotation_from_display_matrix() {
+        let output = parse(
+            r#"{"streams": [{"codec_type": "video", "side_data_list": [{"rotation": 90}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(Some(270), output.rotation());
+    }
+
+    #[test]
+    fn frame_rates() {
+        assert_eq!(Some(30000. / 1001.), parse_frame_rate("30000/1001"));
+        assert_eq!(Some(25.), parse_frame_rate("25/1"));
+        assert_eq!(None, parse_frame_rate("0/0"));
+        assert_eq!(None, parse_frame_rate("25"));
+    }
+
+    #[test]
+    fn iso6709_locations() {
+        assert_eq!(Some((52.52, 13.405)), parse_iso6709("+52.5200+013.4050/"));
+        assert_eq!(
+            Some((-33.8688, -151.2093)),
+            parse_iso6709("-33.8688-151.2093+012.000/")
+        );
+        assert_eq!(None, parse_iso6709("52.52,13.405"));
+    }
+}
@@ -0,0 +1,238 @@
+use std::path::Path;
+use std::process::Command;
+
+use opencv::{core, core::Vector, imgcodecs, prelude::*};
+use shared::models::FotoboekConfig;
+use shared::path_utils::has_extension;
+
+use crate::modules::raw_preview;
+
+const HEIF_EXTENSIONS: [&str; 3] = ["heic", "heif", "avif"];
+const GIF_EXTENSIONS: [&str; 1] = ["gif"];
+
+/// Reads the image at the given path. OpenCV reads JPEG, PNG, WebP and TIFF itself, HEIC/HEIF and
+/// AVIF images are converted with `heif-convert` and of GIF images the first frame is extracted
+/// with `ffmpeg`, because OpenCV does not support these formats. Of RAW images only the embedded
+/// JPEG preview is read, decoding the raw sensor data is not worth the effort.
+///
+/// The EXIF orientation is never applied, see `read_oriented`.
+pub fn read(config: &FotoboekConfig, abs_path: &str, flags: i32) -> Result<Mat, String> {
+    let flags = flags | imgcodecs::IMREAD_IGNORE_ORIENTATION;
+    let path = Path::new(abs_path);
+    let img = if has_extension(path, &config.raw_extensions) {
+        decode(read_raw_preview(abs_path)?, flags)?
+    } else if has_extension(path, &HEIF_EXTENSIONS) {
+        decode(convert_heif_to_png(abs_path)?, flags)?
+    } else if has_extension(path, &GIF_EXTENSIONS) {
+        decode(extract_first_frame(abs_path)?, flags)?
+    } else {
+        imgcodecs::imread(abs_path, flags).map_err(|err| err.to_string())?
+    };
+
+    if img.empty().map_err(|err| err.to_string())? {
+        Err(format!(
+            "Image invalid or format not supported: {}",
+            abs_path
+        ))
+    } else {
+        Ok(img)
+    }
+}
+
+/// Reads the image like `read` and rotates and flips it as given by its EXIF orientation (1-8).
+/// HEIC/HEIF and AVIF images are rotated by `heif-convert` already, as their own transformations
+/// take precedence over the EXIF orientation.
+pub fn read_oriented(
+    config: &FotoboekConfig,
+    abs_path: &str,
+    flags: i32,
+    orientation: Option<i32>,
+) -> Result<Mat, String> {
+    let img = read(config, abs_path, flags)?;
+    match orientation {
+        Some(orientation) if !has_extension(Path::new(abs_path), &HEIF_EXTENSIONS) => {
+            orient(img, orientation)
+        }
+        _ => Ok(img),
+    }
+}
+
+fn orient(img: Mat, orientation: i32) -> Result<Mat, String> {
+    let (rotate_code, flip_code) = orientation_transforms(orientation);
+    let mut img = img;
+    if let Some(rotate_code) = rotate_code {
+        let mut rotated = Mat::default();
+        core::rotate(&img, &mut rotated, rotate_code).map_err(|err| err.to_string())?;
+        img = rotated;
+    }
+    if let Some(flip_code) = flip_code {
+        let mut flipped = Mat::default();
+        core::flip(&img, &mut flipped, flip_code).map_err(|err| err.to_string())?;
+        img = flipped;
+    }
+    Ok(img)
+}
+
+/// Returns the rotation followed by the flip (0 vertical, 1 horizontal) that turn the stored image
+/// upright for the given EXIF orientation. Unknown orientations are ignored.
+fn orientation_transforms(orientation: i32) -> (Option<i32>, Option<i32>) {
+    match orientation {
+        2 => (None, Some(1)),
+        3 => (Some(core::ROTATE_180), None),
+        4 => (None, Some(0)),
+        5 => (Some(core::ROTATE_90_CLOCKWISE), Some(1)),
+        6 => (Some(core::ROTATE_90_CLOCKWISE), None),
+        7 => (Some(core::ROTATE_90_CLOCKWISE), Some(0)),
+        8 => (Some(core::ROTATE_90_COUNTERCLOCKWISE), None),
+        _ => (None, None),
+    }
+}
+
+fn decode(raw: Vec<u8>, flags: i32) -> Result<Mat, String> {
+    let cv_vector: Vector<u8> = Vector::from(raw);
+    imgcodecs::imdecode(&cv_vector, flags).map_err(|err| err.to_string())
+}
+
+fn read_raw_preview(abs_path: &str) -> Result<Vec<u8>, String> {
+    let contents = std::fs::read(abs_path).map_err(|err| err.to_string())?;
+    raw_preview::find_embedded_preview(&contents)
+        .map(|preview| preview.to_vec())
+        .ok_or(format!(
+            "No embedded preview found in RAW file: {}",
+            abs_path
+        ))
+}
+
+fn convert_heif_to_png(abs_path: &str) -> Result<Vec<u8>, String> {
+    let temp_dir = tempdir::TempDir::new("fotoboek_heif").map_err(|err| err.to_string())?;
+    let png_path = temp_dir.path().join("image.png");
+
+    let output = Command::new("heif-convert")
+        .arg(abs_path)
+        .arg(&png_path)
+        .output()
+        .map_err(|err| format!("Failed to run heif-convert: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "heif-convert failed for {}: {}",
+            abs_path,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    std::fs::read(&png_path).map_err(|err| err.to_string())
+}
+
+fn extract_first_frame(abs_path: &str) -> Result<Vec<u8>, String> {
+    let output = Command::new("ffmpeg")
+        .args(&["-v", "error", "-i", abs_path])
+        .args(&["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-"])
+        .output()
+        .map_err(|err| format!("Failed to run ffmpeg: {}", err))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffmpeg failed for {}: {}",
+            abs_path,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FotoboekConfig {
+        FotoboekConfig {
+            media_source_path: "".to_string(),
+            file_storage_path: "".to_string(),
+            webapp_files_path: "".to_string(),
+            worker_pools: vec![],
+            worker_idle_interval_sec: 60,
+            task_lock_timeout_sec: 60,
+            task_max_attempts: 5,
+            task_retry_backoff_sec: 60,
+            fs_watcher_enabled: false,
+            scan_on_startup: false,
+            scan_interval_hours: 0,
+            similarity_max_distance: 0,
+            image_extensions: vec![],
+            raw_extensions: vec!["dng".to_string()],
+            video_extensions: vec![],
+            preview_sizes: vec![],
+            preview_on_demand_max_concurrent: 0,
+        }
+    }
+
+    fn assert_decoded(file_name: &str) {
+        let abs_path = format!(
+            "{}/tests/fixtures/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            file_name
+        );
+        let img = read(&config(), &abs_path, imgcodecs::IMREAD_COLOR).unwrap();
+        assert_eq!((16, 16), (img.cols(), img.rows()));
+    }
+
+    #[test]
+    fn png_images_are_decoded() {
+        assert_decoded("exif.png");
+    }
+
+    #[test]
+    fn webp_images_are_decoded() {
+        assert_decoded("exif.webp");
+    }
+
+    #[test]
+    fn tiff_images_are_decoded() {
+        assert_decoded("exif.tif");
+    }
+
+    #[test]
+    fn heif_images_are_decoded() {
+        assert_decoded("exif.heic");
+    }
+
+    #[test]
+    fn first_frame_of_gif_images_is_decoded() {
+        assert_decoded("no_exif.gif");
+    }
+
+    #[test]
+    fn upright_orientations_are_not_transformed() {
+        assert_eq!((None, None), orientation_transforms(1));
+        assert_eq!((None, None), orientation_transforms(0));
+        assert_eq!((None, None), orientation_transforms(9));
+    }
+
+    #[test]
+    fn rotated_orientations_are_transformed() {
+        assert_eq!((Some(core::ROTATE_180), None), orientation_transforms(3));
+        assert_eq!(
+            (Some(core::ROTATE_90_CLOCKWISE), None),
+            orientation_transforms(6)
+        );
+        assert_eq!(
+            (Some(core::ROTATE_90_COUNTERCLOCKWISE), None),
+            orientation_transforms(8)
+        );
+    }
+
+    #[test]
+    fn mirrored_orientations_are_transformed() {
+        assert_eq!((None, Some(1)), orientation_transforms(2));
+        assert_eq!((None, Some(0)), orientation_transforms(4));
+        // Transpose and transverse
+        assert_eq!(
+            (Some(core::ROTATE_90_CLOCKWISE), Some(1)),
+            orientation_transforms(5)
+        );
+        assert_eq!(
+            (Some(core::ROTATE_90_CLOCKWISE), Some(0)),
+            orientation_transforms(7)
+        );
+    }
+}
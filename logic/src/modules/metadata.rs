@@ -1,5 +1,5 @@
 use std::io::{Cursor, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use chrono::NaiveDateTime;
@@ -8,29 +8,26 @@ use log::warn;
 use mp4::{Mp4Reader, TrackType};
 use opencv::imgcodecs;
 use opencv::prelude::MatTraitManual;
-use rexif::{ExifData, ExifTag};
+use rexif::{ExifData, ExifTag, TagValue};
 use sha256::digest_bytes;
 use regex::{Captures, Regex};
+use tokio::task;
 
 use persistance::models::{File, FileMetadata, Task};
-use persistance::FotoboekDatabase;
+use persistance::{fs, FotoboekDatabase};
 use shared::models::FotoboekConfig;
 use shared::path_utils;
 use shared::path_utils::rel_to_abs;
 
+use crate::modules::ffprobe::{self, FfprobeOutput};
+use crate::modules::{exif, image_reader, phash, preview};
+
 pub const MODULE_ID: &str = "metadata";
 
 pub async fn create_tasks_on_new_file(db: &FotoboekDatabase, file: &File) -> Result<(), String> {
-    Task {
-        id: None,
-        file_id: file.id.unwrap(),
-        module: MODULE_ID.into(),
-        priority: 100,
-        max_worker_id: 1024,
-        work_started_at: chrono::NaiveDateTime::from_timestamp(0, 0),
-    }
-    .insert(db)
-    .await?;
+    Task::new(file.id.unwrap(), MODULE_ID, 100)
+        .insert(db)
+        .await?;
 
     Ok(())
 }
@@ -57,20 +54,46 @@ trait MetadataExtractor {
     fn video_duration(&self) -> Option<i32> {
         None
     }
+    fn video_codec(&self) -> Option<String> {
+        None
+    }
+    fn audio_codec(&self) -> Option<String> {
+        None
+    }
+    fn video_frame_rate(&self) -> Option<f32> {
+        None
+    }
+    fn video_rotation(&self) -> Option<i32> {
+        None
+    }
+    fn exif_orientation(&self) -> Option<i32> {
+        None
+    }
     fn gps_lat_lon(&self) -> Option<(f32, f32)> {
         None
     }
 }
 
 struct ImageMetadataExtractor {
+    config: FotoboekConfig,
     abs_path: String,
     exif_opt: Option<ExifData>,
 }
 
 impl ImageMetadataExtractor {
-    fn parse(abs_path: String, file_contents: &[u8]) -> Box<dyn MetadataExtractor> {
-        let exif_opt = rexif::parse_buffer_quiet(&file_contents).0.ok();
-        Box::new(ImageMetadataExtractor { abs_path, exif_opt })
+    fn parse(
+        config: &FotoboekConfig,
+        abs_path: String,
+        file_contents: &[u8],
+    ) -> Box<dyn MetadataExtractor> {
+        let exif_opt = exif::find_exif(file_contents)
+            .map(|exif_contents| rexif::parse_buffer_quiet(exif_contents).0.ok())
+            .flatten();
+        Box::new(ImageMetadataExtractor {
+            config: config.clone(),
+            abs_path,
+            exif_opt,
+        })
     }
 
     fn get_exif_value(&self, tag: ExifTag) -> Option<String> {
@@ -85,15 +108,41 @@ impl ImageMetadataExtractor {
             })
             .flatten()
     }
+
+    fn get_exif_u16_value(&self, tag: ExifTag) -> Option<u16> {
+        self.exif_opt.as_ref().and_then(|exif| {
+            exif.entries
+                .iter()
+                .filter(|entry| entry.tag == tag)
+                .find_map(|entry| match &entry.value {
+                    TagValue::U16(values) => values.first().copied(),
+                    _ => None,
+                })
+        })
+    }
 }
 
 impl MetadataExtractor for ImageMetadataExtractor {
+    /// Returns the resolution after the EXIF orientation is applied, i.e. as the image is shown.
     fn resolution(&self) -> (i32, i32) {
-        let img = imgcodecs::imread(&self.abs_path, imgcodecs::IMREAD_GRAYSCALE)
-            .expect("Image not found or invalid");
-        let size = img.size().expect("Failed to get image size");
-
-        (size.width, size.height)
+        let size_opt = image_reader::read_oriented(
+            &self.config,
+            &self.abs_path,
+            imgcodecs::IMREAD_GRAYSCALE,
+            self.exif_orientation(),
+        )
+        .and_then(|img| img.size().map_err(|err| err.to_string()));
+
+        match size_opt {
+            Ok(size) => (size.width, size.height),
+            Err(err) => {
+                warn!(
+                    "Could not read resolution of image file, will use (0,0): {}",
+                    err
+                );
+                (0, 0)
+            }
+        }
     }
 
     fn creation_date(&self) -> Option<NaiveDateTime> {
@@ -125,6 +174,10 @@ impl MetadataExtractor for ImageMetadataExtractor {
     fn exif_iso(&self) -> Option<String> {
         self.get_exif_value(ExifTag::ISOSpeedRatings)
     }
+    fn exif_orientation(&self) -> Option<i32> {
+        self.get_exif_u16_value(ExifTag::Orientation)
+            .map(|orientation| orientation as i32)
+    }
     fn gps_lat_lon(&self) -> Option<(f32, f32)> {
         let lat_option = self
             .get_exif_value(ExifTag::GPSLatitude)
@@ -146,6 +199,7 @@ impl MetadataExtractor for ImageMetadataExtractor {
 struct VideoMetadataExtractor {
     abs_path: String,
     mp4: Option<Mp4Reader<Cursor<Vec<u8>>>>,
+    probe: Option<FfprobeOutput>,
 }
 
 impl VideoMetadataExtractor {
@@ -153,7 +207,13 @@ impl VideoMetadataExtractor {
         let size = file_contents.len() as u64;
         let cursor = Cursor::new(file_contents);
         let mp4 = Mp4Reader::read_header(cursor, size).ok();
-        Box::new(VideoMetadataExtractor { abs_path, mp4 })
+        // Codecs, frame rate and rotation are read with ffprobe, same as for other containers
+        let probe = probe(&abs_path);
+        Box::new(VideoMetadataExtractor {
+            abs_path,
+            mp4,
+            probe,
+        })
     }
 }
 
@@ -211,7 +271,94 @@ impl MetadataExtractor for VideoMetadataExtractor {
     }
 
     fn video_duration(&self) -> Option<i32> {
-        self.mp4.as_ref().map(|mp4| mp4.duration().as_secs() as i32)
+        self.mp4
+            .as_ref()
+            .map(|mp4| mp4.duration().as_secs() as i32)
+            .or_else(|| self.probe.as_ref().and_then(|probe| probe.duration()))
+    }
+
+    fn video_codec(&self) -> Option<String> {
+        self.probe.as_ref().and_then(|probe| probe.video_codec())
+    }
+
+    fn audio_codec(&self) -> Option<String> {
+        self.probe.as_ref().and_then(|probe| probe.audio_codec())
+    }
+
+    fn video_frame_rate(&self) -> Option<f32> {
+        self.probe.as_ref().and_then(|probe| probe.frame_rate())
+    }
+
+    fn video_rotation(&self) -> Option<i32> {
+        self.probe.as_ref().and_then(|probe| probe.rotation())
+    }
+}
+
+/// Reads the metadata of all other containers than MP4 with ffprobe.
+struct FfprobeMetadataExtractor {
+    abs_path: String,
+    probe: Option<FfprobeOutput>,
+}
+
+impl FfprobeMetadataExtractor {
+    fn parse(abs_path: String) -> Box<dyn MetadataExtractor> {
+        let probe = probe(&abs_path);
+        Box::new(FfprobeMetadataExtractor { abs_path, probe })
+    }
+}
+
+fn probe(abs_path: &str) -> Option<FfprobeOutput> {
+    ffprobe::probe(abs_path)
+        .map_err(|err| warn!("Could not probe video file: {}", err))
+        .ok()
+}
+
+impl MetadataExtractor for FfprobeMetadataExtractor {
+    fn resolution(&self) -> (i32, i32) {
+        self.probe
+            .as_ref()
+            .and_then(|probe| probe.resolution())
+            .unwrap_or_else(|| {
+                warn!(
+                    "Could not extract resolution from video file, will use (0,0): {}",
+                    self.abs_path
+                );
+                (0, 0)
+            })
+    }
+
+    fn creation_date(&self) -> Option<NaiveDateTime> {
+        self.probe.as_ref().and_then(|probe| probe.creation_date())
+    }
+
+    fn filename_date(&self) -> Option<NaiveDateTime> {
+        let path_buf = PathBuf::from(&self.abs_path);
+        let filename = path_utils::get_filename(&path_buf);
+        search_for_date_time_in_filename(filename)
+    }
+
+    fn video_duration(&self) -> Option<i32> {
+        self.probe.as_ref().and_then(|probe| probe.duration())
+    }
+
+    fn video_codec(&self) -> Option<String> {
+        self.probe.as_ref().and_then(|probe| probe.video_codec())
+    }
+
+    fn audio_codec(&self) -> Option<String> {
+        self.probe.as_ref().and_then(|probe| probe.audio_codec())
+    }
+
+    fn video_frame_rate(&self) -> Option<f32> {
+        self.probe.as_ref().and_then(|probe| probe.frame_rate())
+    }
+
+    fn video_rotation(&self) -> Option<i32> {
+        self.probe.as_ref().and_then(|probe| probe.rotation())
+    }
+
+    fn gps_lat_lon(&self) -> Option<(f32, f32)> {
+        self.probe.as_ref().and_then(|probe| probe.gps_lat_lon())
     }
 }
 
@@ -223,44 +370,116 @@ pub async fn run_task(
     let file = File::by_id(db, task.file_id).await?;
     let abs_path = rel_to_abs(config, &file.rel_path);
 
+    // Reading and hashing the whole file and probing videos takes long, it must not block the
+    // async runtime that renews the task lock
+    let config_copy = config.clone();
+    let file_id = task.file_id;
+    let file_type = file.file_type.clone();
+    let metadata =
+        task::spawn_blocking(move || extract_metadata(&config_copy, file_id, &file_type, abs_path))
+            .await
+            .map_err(|err| err.to_string())??;
+
+    let previous_metadata = FileMetadata::by_file_id(db, task.file_id).await;
+    let previous_file_hash = previous_metadata
+        .as_ref()
+        .map(|previous_metadata| previous_metadata.file_hash.clone())
+        .filter(|previous_file_hash| *previous_file_hash != metadata.file_hash);
+    // Previews of unchanged files were generated without the orientation, if it was not known yet
+    let orientation = metadata.exif_orientation.unwrap_or(1);
+    let is_orientation_changed = previous_file_hash.is_none()
+        && previous_metadata
+            .map(|previous_metadata| previous_metadata.exif_orientation.unwrap_or(1) != orientation)
+            .unwrap_or(false);
+    let file_hash = metadata.file_hash.clone();
+
+    metadata.save(db).await?;
+
+    // The file was modified, previews and videos of the previous contents are obsolete
+    if let Some(previous_file_hash) = previous_file_hash {
+        crate::source_files::delete_unreferenced_files(db, config, &previous_file_hash).await?;
+    }
+    if is_orientation_changed {
+        fs::delete_previews(config, &file_hash)?;
+        if is_task_missing(db, task.file_id, preview::MODULE_ID).await? {
+            preview::create_tasks_on_new_file(db, &file).await?;
+        }
+        if is_task_missing(db, task.file_id, phash::MODULE_ID).await? {
+            phash::create_tasks_on_new_file(db, &file).await?;
+        }
+        crate::worker::notify_tasks_enqueued();
+    }
+    Ok(())
+}
+
+/// Returns true if the file has no task of the module. An existing task is kept, and retried if it
+/// failed, as the file changed since.
+async fn is_task_missing(
+    db: &FotoboekDatabase,
+    file_id: i32,
+    module: &str,
+) -> Result<bool, String> {
+    match Task::by_file_id_and_module(db, file_id, module).await {
+        Some(task) if task.is_failed() => {
+            task.retry(db).await?;
+            Ok(false)
+        }
+        Some(_) => Ok(false),
+        None => Ok(true),
+    }
+}
+
+/// Extracts the metadata of the file, blocks for a long time as the whole file is read.
+fn extract_metadata(
+    config: &FotoboekConfig,
+    file_id: i32,
+    file_type: &str,
+    abs_path: String,
+) -> Result<FileMetadata, String> {
     let (file_size_bytes, file_date) = get_file_size_and_date(&abs_path)?;
+    let (_, file_modified_at) = get_file_size_and_modified_date(&abs_path)?;
     let file_contents = read_file_contents(&abs_path, file_size_bytes as usize);
     let file_hash = digest_bytes(&file_contents);
 
-    let metadata = {
-        let metadata_extractor = match file.file_type.as_str() {
-            "IMAGE" => ImageMetadataExtractor::parse(abs_path, &file_contents),
-            "VIDEO" => VideoMetadataExtractor::parse(abs_path, file_contents),
-            _ => panic!("Unsupported file type: {}", file.file_type),
-        };
-
-        let (resolution_x, resolution_y) = metadata_extractor.resolution();
-        let creation_date = metadata_extractor.creation_date();
-        let filename_date = metadata_extractor.filename_date();
-        let exif_gps_lat_lon = metadata_extractor.gps_lat_lon();
-
-        FileMetadata {
-            file_id: Some(task.file_id),
-            file_hash,
-            file_size_bytes,
-            file_date,
-            resolution_x,
-            resolution_y,
-            exif_date: creation_date,
-            exif_camera_manufacturer: metadata_extractor.camera_manufacturer(),
-            exif_camera_model: metadata_extractor.camera_model(),
-            exif_aperture: metadata_extractor.exif_aperture(),
-            exif_exposure_time: metadata_extractor.exif_exposure_time(),
-            exif_iso: metadata_extractor.exif_iso(),
-            exif_gps_lat: exif_gps_lat_lon.map(|lat_lon| lat_lon.0),
-            exif_gps_lon: exif_gps_lat_lon.map(|lat_lon| lat_lon.1),
-            effective_date: creation_date.or(filename_date).unwrap_or(file_date),
-            filename_date,
+    let metadata_extractor = match file_type {
+        "IMAGE" => ImageMetadataExtractor::parse(config, abs_path, &file_contents),
+        "VIDEO" if path_utils::has_extension(Path::new(&abs_path), &["mp4"]) => {
+            VideoMetadataExtractor::parse(abs_path, file_contents)
         }
+        "VIDEO" => FfprobeMetadataExtractor::parse(abs_path),
+        _ => panic!("Unsupported file type: {}", file_type),
     };
 
-    metadata.save(db).await?;
-    Ok(())
+    let (resolution_x, resolution_y) = metadata_extractor.resolution();
+    let creation_date = metadata_extractor.creation_date();
+    let filename_date = metadata_extractor.filename_date();
+    let exif_gps_lat_lon = metadata_extractor.gps_lat_lon();
+
+    Ok(FileMetadata {
+        file_id: Some(file_id),
+        file_hash,
+        file_size_bytes,
+        file_date,
+        resolution_x,
+        resolution_y,
+        exif_date: creation_date,
+        exif_camera_manufacturer: metadata_extractor.camera_manufacturer(),
+        exif_camera_model: metadata_extractor.camera_model(),
+        exif_aperture: metadata_extractor.exif_aperture(),
+        exif_exposure_time: metadata_extractor.exif_exposure_time(),
+        exif_iso: metadata_extractor.exif_iso(),
+        exif_gps_lat: exif_gps_lat_lon.map(|lat_lon| lat_lon.0),
+        exif_gps_lon: exif_gps_lat_lon.map(|lat_lon| lat_lon.1),
+        effective_date: creation_date.or(filename_date).unwrap_or(file_date),
+        filename_date,
+        video_duration: metadata_extractor.video_duration(),
+        video_codec: metadata_extractor.video_codec(),
+        audio_codec: metadata_extractor.audio_codec(),
+        video_frame_rate: metadata_extractor.video_frame_rate(),
+        video_rotation: metadata_extractor.video_rotation(),
+        exif_orientation: metadata_extractor.exif_orientation(),
+        file_modified_at: Some(file_modified_at),
+    })
 }
 
 fn read_file_contents(abs_path: &String, file_size: usize) -> Vec<u8> {
@@ -273,8 +492,7 @@ fn read_file_contents(abs_path: &String, file_size: usize) -> Vec<u8> {
 }
 
 fn get_file_size_and_date(abs_path: &String) -> Result<(i32, NaiveDateTime), String> {
-    let metadata = std::fs::metadata(abs_path)
-        .map_err(|err| format!("Failed to get fs metadata: {}, abs_path: {}", err, abs_path))?;
+    let metadata = get_fs_metadata(abs_path)?;
 
     let file_size = metadata.len() as i32;
     let file_date_time: chrono::DateTime<chrono::Utc> = metadata
@@ -285,6 +503,27 @@ fn get_file_size_and_date(abs_path: &String) -> Result<(i32, NaiveDateTime), Str
     Ok((file_size, file_date_time.naive_utc()))
 }
 
+/// Returns the size and the last modification date of the file. Both are used to detect whether
+/// a file changed since its metadata was extracted.
+pub fn get_file_size_and_modified_date(
+    abs_path: &String,
+) -> Result<(i32, NaiveDateTime), String> {
+    let metadata = get_fs_metadata(abs_path)?;
+
+    let file_size = metadata.len() as i32;
+    let modified_date_time: chrono::DateTime<chrono::Utc> = metadata
+        .modified()
+        .map_err(|err| format!("Failed to get modified date: {:?}", err))?
+        .into();
+
+    Ok((file_size, modified_date_time.naive_utc()))
+}
+
+fn get_fs_metadata(abs_path: &String) -> Result<std::fs::Metadata, String> {
+    std::fs::metadata(abs_path)
+        .map_err(|err| format!("Failed to get fs metadata: {}, abs_path: {}", err, abs_path))
+}
+
 fn search_for_date_time_in_filename(filename: String) -> Option<NaiveDateTime> {
     lazy_static! {
         static ref RE: Regex = Regex::new(
@@ -4,10 +4,27 @@ use persistance::FotoboekDatabase;
 use shared::models::FotoboekConfig;
 use std::time::Instant;
 
+mod exif;
+mod ffprobe;
+mod image_reader;
 mod metadata;
+mod phash;
 mod preview;
+mod raw_preview;
 mod transcode;
 
+pub use metadata::get_file_size_and_modified_date;
+pub use preview::{create_tasks_on_missing_previews, OnDemandPreviews};
+pub use transcode::MODULE_ID as TRANSCODE_MODULE_ID;
+
+/// Ids of all modules, each of them must be run by a worker pool.
+pub const MODULE_IDS: [&str; 4] = [
+    metadata::MODULE_ID,
+    preview::MODULE_ID,
+    transcode::MODULE_ID,
+    phash::MODULE_ID,
+];
+
 pub async fn create_tasks_on_new_file(
     db: &FotoboekDatabase,
     file: &File,
@@ -15,9 +32,18 @@ pub async fn create_tasks_on_new_file(
     metadata::create_tasks_on_new_file(db, file).await?;
     preview::create_tasks_on_new_file(db, file).await?;
     transcode::create_tasks_on_new_file(db, file).await?;
+    phash::create_tasks_on_new_file(db, file).await?;
+    crate::worker::notify_tasks_enqueued();
     Ok(())
 }
 
+/// Discards all pending tasks of the file and creates them anew, e.g. because the file was
+/// modified.
+pub async fn recreate_tasks(db: &FotoboekDatabase, file: &File) -> Result<(), String> {
+    Task::delete_by_file_id(db, file.id.unwrap()).await?;
+    create_tasks_on_new_file(db, file).await
+}
+
 pub async fn run_task(
     db: &FotoboekDatabase,
     config: &FotoboekConfig,
@@ -29,6 +55,7 @@ pub async fn run_task(
         metadata::MODULE_ID => metadata::run_task(db, config, task).await,
         preview::MODULE_ID => preview::run_task(db, config, task).await,
         transcode::MODULE_ID => transcode::run_task(db, config, task).await,
+        phash::MODULE_ID => phash::run_task(db, config, task).await,
         &_ => Err(format!("Unknown module in {:?}", task).into()),
     }?;
 
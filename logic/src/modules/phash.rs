@@ -0,0 +1,123 @@
+use opencv::{core::Size, imgcodecs, imgproc, prelude::*};
+use std::path::Path;
+use tokio::task;
+
+use persistance::models::{File, FileMetadata, PerceptualHash, Task};
+use persistance::{fs, FotoboekDatabase};
+use shared::models::{FotoboekConfig, PREVIEW_SIZE_SMALL};
+
+use crate::modules::{image_reader, preview};
+
+pub const MODULE_ID: &str = "phash";
+
+const HASH_WIDTH: i32 = 8;
+const HASH_HEIGHT: i32 = 8;
+
+pub async fn create_tasks_on_new_file(db: &FotoboekDatabase, file: &File) -> Result<(), String> {
+    Task::new(file.id.unwrap(), MODULE_ID, 400)
+        .depends_on(preview::MODULE_ID)
+        .insert(db)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn run_task(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    task: &Task,
+) -> Result<(), String> {
+    let metadata = FileMetadata::by_file_id(db, task.file_id)
+        .await
+        .ok_or("File metadata not found".to_string())?;
+    let preview_size = config
+        .preview_size(PREVIEW_SIZE_SMALL)
+        .ok_or("Small preview size not configured".to_string())?;
+    let preview_path = fs::file_preview_path(config, &metadata.file_hash, preview_size);
+    if !Path::new(&preview_path).exists() {
+        return Err("Small preview not found".to_string());
+    }
+
+    // Decoding must not block the async runtime that renews the task lock
+    let config_copy = config.clone();
+    let dhash = task::spawn_blocking(move || dhash_by_path(&config_copy, &preview_path))
+        .await
+        .map_err(|err| err.to_string())??;
+    PerceptualHash {
+        file_id: Some(task.file_id),
+        dhash: dhash as i64,
+    }
+    .save(db)
+    .await
+}
+
+/// Calculates the difference hash (dHash) of the image: The image is reduced to a grayscale image
+/// of 9x8 pixels and each bit of the hash tells whether a pixel is brighter than its right
+/// neighbour.
+fn dhash_by_path(config: &FotoboekConfig, path: &str) -> Result<u64, String> {
+    let img = image_reader::read(config, path, imgcodecs::IMREAD_GRAYSCALE)?;
+
+    let mut resize_out = Mat::default();
+    imgproc::resize(
+        &img,
+        &mut resize_out,
+        Size {
+            width: HASH_WIDTH + 1,
+            height: HASH_HEIGHT,
+        },
+        0.,
+        0.,
+        imgproc::INTER_AREA,
+    )
+    .map_err(|err| err.to_string())?;
+
+    let mut pixels = Vec::with_capacity(((HASH_WIDTH + 1) * HASH_HEIGHT) as usize);
+    for row in 0..HASH_HEIGHT {
+        for col in 0..(HASH_WIDTH + 1) {
+            let pixel = resize_out
+                .at_2d::<u8>(row, col)
+                .map_err(|err| err.to_string())?;
+            pixels.push(*pixel);
+        }
+    }
+    Ok(dhash_by_pixels(&pixels))
+}
+
+/// Calculates the dHash of 9x8 grayscale pixels, given row by row.
+fn dhash_by_pixels(pixels: &[u8]) -> u64 {
+    let row_len = (HASH_WIDTH + 1) as usize;
+    pixels
+        .chunks(row_len)
+        .flat_map(|row| row.windows(2).map(|pair| pair[0] > pair[1]))
+        .fold(0, |hash, is_brighter| (hash << 1) | is_brighter as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dhash_of_uniform_image_is_zero() {
+        assert_eq!(0, dhash_by_pixels(&[128; 72]));
+    }
+
+    #[test]
+    fn dhash_of_gradient_images() {
+        let falling: Vec<u8> = (0..72).map(|i| 255 - (i % 9) as u8 * 10).collect();
+        assert_eq!(u64::MAX, dhash_by_pixels(&falling));
+
+        let rising: Vec<u8> = (0..72).map(|i| (i % 9) as u8 * 10).collect();
+        assert_eq!(0, dhash_by_pixels(&rising));
+    }
+
+    #[test]
+    fn dhash_bits_are_ordered_row_by_row() {
+        let mut pixels = [0u8; 72];
+        pixels[0] = 1;
+        assert_eq!(1 << 63, dhash_by_pixels(&pixels));
+
+        let mut pixels = [0u8; 72];
+        pixels[7 * 9 + 7] = 1;
+        assert_eq!(1, dhash_by_pixels(&pixels));
+    }
+}
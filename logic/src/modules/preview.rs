@@ -1,22 +1,69 @@
+use log::{debug, info};
 use persistance::models::{File, FileMetadata, Task};
-use persistance::FotoboekDatabase;
-use shared::models::FotoboekConfig;
+use persistance::{fs, FotoboekDatabase};
+use shared::models::{FotoboekConfig, PreviewSize};
 use shared::path_utils::rel_to_abs;
+use std::collections::{HashMap, HashSet};
 use std::string::ToString;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task;
+
+use crate::modules::metadata;
 
 pub const MODULE_ID: &str = "preview";
 
 pub async fn create_tasks_on_new_file(db: &FotoboekDatabase, file: &File) -> Result<(), String> {
-    Task {
-        id: None,
-        file_id: file.id.unwrap(),
-        module: MODULE_ID.into(),
-        priority: 200,
-        max_worker_id: 1024,
-        work_started_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+    create_task(db, file.id.unwrap()).await
+}
+
+/// Deletes previews of sizes and formats that are no longer configured and creates preview tasks
+/// for all files that miss one of the configured previews, e.g. because a preview size was added
+/// to the config. Returns the number of created tasks.
+pub async fn create_tasks_on_missing_previews(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+) -> Result<usize, String> {
+    let config_copy = config.clone();
+    let deleted_count = task::spawn_blocking(move || fs::delete_orphaned_previews(&config_copy))
+        .await
+        .map_err(|err| err.to_string())??;
+    if deleted_count > 0 {
+        info!(
+            "Deleted {} previews of sizes that are no longer configured",
+            deleted_count
+        );
     }
-    .insert(db)
-    .await?;
+
+    let file_ids_with_task: HashSet<i32> = Task::all(db)
+        .await
+        .into_iter()
+        .filter(|task| task.module == MODULE_ID)
+        .map(|task| task.file_id)
+        .collect();
+
+    let mut created_count = 0;
+    for metadata in FileMetadata::all(db).await {
+        let file_id = metadata.file_id.unwrap();
+        if file_ids_with_task.contains(&file_id) || fs::previews_exist(config, &metadata.file_hash)
+        {
+            continue;
+        }
+        create_task(db, file_id).await?;
+        created_count += 1;
+    }
+    if created_count > 0 {
+        crate::worker::notify_tasks_enqueued();
+    }
+    Ok(created_count)
+}
+
+async fn create_task(db: &FotoboekDatabase, file_id: i32) -> Result<(), String> {
+    Task::new(file_id, MODULE_ID, 200)
+        .depends_on(metadata::MODULE_ID)
+        .insert(db)
+        .await?;
 
     Ok(())
 }
@@ -26,25 +73,187 @@ pub async fn run_task(
     config: &FotoboekConfig,
     task: &Task,
 ) -> Result<(), String> {
-    let metadata = FileMetadata::by_file_id(db, task.file_id).await.ok_or(
-        "File metadata not found, very likely because the metadata task did not finish yet"
-            .to_string(),
-    )?;
+    let metadata = FileMetadata::by_file_id(db, task.file_id)
+        .await
+        .ok_or("File metadata not found".to_string())?;
     let file = File::by_id(db, task.file_id).await?;
+    let preview_sizes: Vec<PreviewSize> = config
+        .preview_sizes
+        .iter()
+        .filter(|preview_size| !fs::preview_exists(config, &metadata.file_hash, preview_size))
+        .cloned()
+        .collect();
+    let with_sprite =
+        file.file_type == "VIDEO" && !fs::video_sprite_exists(config, &metadata.file_hash);
+    if preview_sizes.is_empty() && !with_sprite {
+        debug!("Previews of {} already exist, skipping", file.rel_path);
+        return Ok(());
+    }
     let abs_path = rel_to_abs(config, &file.rel_path);
 
-    match file.file_type.as_str() {
-        "IMAGE" => image::run_task(config, &abs_path, &metadata.file_hash),
-        "VIDEO" => video::run_task(config, &abs_path, &metadata.file_hash),
-        _ => panic!("Unsupported file type: {}", file.file_type),
+    // Decoding, resizing and seeking in videos takes long, it must not block the async runtime
+    // that renews the task lock
+    let config_copy = config.clone();
+    task::spawn_blocking(move || {
+        let preview_sizes: Vec<&PreviewSize> = preview_sizes.iter().collect();
+        generate(
+            &config_copy,
+            &file.file_type,
+            &abs_path,
+            &metadata,
+            &preview_sizes,
+            with_sprite,
+        )
+    })
+    .await
+    .map_err(|err| err.to_string())?
+}
+
+fn generate(
+    config: &FotoboekConfig,
+    file_type: &str,
+    abs_path: &String,
+    metadata: &FileMetadata,
+    preview_sizes: &[&PreviewSize],
+    with_sprite: bool,
+) -> Result<(), String> {
+    match file_type {
+        "IMAGE" => image::run_task(
+            config,
+            abs_path,
+            &metadata.file_hash,
+            metadata.exif_orientation,
+            preview_sizes,
+        ),
+        "VIDEO" => video::run_task(
+            config,
+            abs_path,
+            &metadata.file_hash,
+            metadata.video_duration,
+            preview_sizes,
+            with_sprite,
+        ),
+        _ => panic!("Unsupported file type: {}", file_type),
+    }
+}
+
+/// Generates previews while a user is waiting for them, instead of waiting for a worker to reach
+/// the preview task. The number of concurrent generations is limited, so that a folder full of
+/// missing previews does not starve the server. Previews that failed to be generated, on demand or
+/// by their task, are not retried on every request.
+pub struct OnDemandPreviews {
+    semaphore: Semaphore,
+    /// When the generation of a preview, keyed by file hash and size name, failed last
+    failed_at: Mutex<HashMap<String, Instant>>,
+    retry_delay: Duration,
+}
+
+impl OnDemandPreviews {
+    pub fn new(config: &FotoboekConfig) -> OnDemandPreviews {
+        OnDemandPreviews {
+            semaphore: Semaphore::new(config.preview_on_demand_max_concurrent),
+            failed_at: Mutex::new(HashMap::new()),
+            retry_delay: Duration::from_secs(config.task_retry_backoff_sec as u64),
+        }
+    }
+
+    /// Generates the preview of the given size, unless it was generated in the meantime or its
+    /// generation failed recently. Once all previews of the file exist, its queued preview task is
+    /// removed.
+    pub async fn generate(
+        &self,
+        db: &FotoboekDatabase,
+        config: &FotoboekConfig,
+        metadata: &FileMetadata,
+        preview_size: &PreviewSize,
+    ) -> Result<(), String> {
+        if config.preview_on_demand_max_concurrent == 0 {
+            return Err("On-demand preview generation is disabled".to_string());
+        }
+        let file_id = metadata.file_id.unwrap();
+        let failure_key = format!("{}-{}", metadata.file_hash, preview_size.name);
+        if self.failed_recently(&failure_key) {
+            debug!("Generating {} failed recently, skipping", failure_key);
+            return Ok(());
+        }
+        if let Some(task) = Task::by_file_id_and_module(db, file_id, MODULE_ID).await {
+            if task.is_failed() || task.is_backing_off() {
+                debug!(
+                    "Preview task of file {} failed, skipping {:?}",
+                    file_id, task
+                );
+                return Ok(());
+            }
+        }
+        let file = File::by_id(db, file_id).await?;
+
+        {
+            let _permit = self
+                .semaphore
+                .acquire()
+                .await
+                .map_err(|err| err.to_string())?;
+            if !fs::preview_exists(config, &metadata.file_hash, preview_size) {
+                let abs_path = rel_to_abs(config, &file.rel_path);
+                let config_copy = config.clone();
+                let file_type = file.file_type.clone();
+                let metadata = metadata.clone();
+                let preview_size = preview_size.clone();
+                let result = task::spawn_blocking(move || {
+                    generate(
+                        &config_copy,
+                        &file_type,
+                        &abs_path,
+                        &metadata,
+                        &[&preview_size],
+                        false,
+                    )
+                })
+                .await
+                .map_err(|err| err.to_string())
+                .and_then(|result| result);
+                if result.is_err() {
+                    self.failed_at
+                        .lock()
+                        .unwrap()
+                        .insert(failure_key, Instant::now());
+                }
+                result?;
+            }
+        }
+
+        // The sprite sheet of videos is left to the task
+        let is_sprite_missing =
+            file.file_type == "VIDEO" && !fs::video_sprite_exists(config, &metadata.file_hash);
+        if fs::previews_exist(config, &metadata.file_hash) && !is_sprite_missing {
+            if let Some(task) = Task::by_file_id_and_module(db, file_id, MODULE_ID).await {
+                // A task a worker is working on is left to the worker
+                if task.clone().delete_if_unlocked(db, config).await? {
+                    debug!("All previews of file {} exist, removed {:?}", file_id, task);
+                    crate::worker::notify_tasks_enqueued();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if generating the preview failed less than the retry delay ago.
+    fn failed_recently(&self, failure_key: &str) -> bool {
+        let mut failed_at = self.failed_at.lock().unwrap();
+        failed_at.retain(|_, failed_at| failed_at.elapsed() < self.retry_delay);
+        failed_at.contains_key(failure_key)
     }
 }
 
 mod image {
+    use std::process::Command;
+
     use opencv::{core::Size, imgcodecs, imgproc, prelude::*};
 
     use persistance::fs;
-    use shared::models::{FotoboekConfig, PreviewSize};
+    use shared::models::{FotoboekConfig, PreviewFit, PreviewFormat, PreviewSize};
+
+    use crate::modules::image_reader;
 
     const SIZE_ZERO: Size = Size {
         height: 0,
@@ -55,30 +264,19 @@ mod image {
         config: &FotoboekConfig,
         abs_path: &String,
         file_hash: &String,
+        orientation: Option<i32>,
+        preview_sizes: &[&PreviewSize],
     ) -> Result<(), String> {
-        let large_preview_bytes =
-            resize_by_path(abs_path, &PreviewSize::Large).expect("Resize large failed");
-        fs::store_preview(config, file_hash, &PreviewSize::Large, &large_preview_bytes)?;
-
-        let small_preview_bytes =
-            resize_by_vec(large_preview_bytes, &PreviewSize::Small).expect("Resize small failed");
-        fs::store_preview(config, file_hash, &PreviewSize::Small, &small_preview_bytes)
-    }
-
-    fn resize_by_vec(raw: Vec<u8>, preview_size: &PreviewSize) -> Result<Vec<u8>, ()> {
-        let cv_vector: opencv::core::Vector<u8> = opencv::core::Vector::from(raw);
         let img =
-            imgcodecs::imdecode(&cv_vector, imgcodecs::IMREAD_COLOR).expect("Preview invalid");
-        resize_by_cv_mat(&img, preview_size)
-    }
-
-    fn resize_by_path(path: &str, preview_size: &PreviewSize) -> Result<Vec<u8>, ()> {
-        let img =
-            imgcodecs::imread(path, imgcodecs::IMREAD_COLOR).expect("Image not found or invalid");
-        resize_by_cv_mat(&img, preview_size)
+            image_reader::read_oriented(config, abs_path, imgcodecs::IMREAD_COLOR, orientation)?;
+        for preview_size in preview_sizes {
+            let preview_bytes = resize_by_cv_mat(&img, preview_size)?;
+            fs::store_preview(config, file_hash, preview_size, &preview_bytes)?;
+        }
+        Ok(())
     }
 
-    pub fn resize_by_cv_mat(img: &Mat, preview_size: &PreviewSize) -> Result<Vec<u8>, ()> {
+    pub fn resize_by_cv_mat(img: &Mat, preview_size: &PreviewSize) -> Result<Vec<u8>, String> {
         let scale_factor = to_scale_factor(preview_size, img.size().expect("Image size"));
 
         let mut resize_out = Mat::default();
@@ -92,17 +290,64 @@ mod image {
         )
         .expect("Resize failed");
 
+        match preview_size.format {
+            PreviewFormat::Webp => encode(
+                &resize_out,
+                ".webp",
+                imgcodecs::IMWRITE_WEBP_QUALITY,
+                preview_size.quality,
+            ),
+            PreviewFormat::Jpeg => encode(
+                &resize_out,
+                ".jpg",
+                imgcodecs::IMWRITE_JPEG_QUALITY,
+                preview_size.quality,
+            ),
+            PreviewFormat::Avif => encode_avif(&resize_out, preview_size.quality),
+        }
+    }
+
+    pub fn encode(
+        img: &Mat,
+        extension: &str,
+        quality_param: i32,
+        quality: i32,
+    ) -> Result<Vec<u8>, String> {
         let mut encode_params = opencv::core::Vector::<i32>::new();
-        encode_params.push(opencv::imgcodecs::IMWRITE_WEBP_QUALITY);
-        encode_params.push(85);
+        encode_params.push(quality_param);
+        encode_params.push(quality);
 
         let mut encode_out = opencv::core::Vector::<u8>::new();
-        imgcodecs::imencode(".webp", &resize_out, &mut encode_out, &encode_params)
-            .expect("Encode failed");
+        imgcodecs::imencode(extension, img, &mut encode_out, &encode_params)
+            .map_err(|err| err.to_string())?;
 
         Ok(encode_out.to_vec())
     }
 
+    /// OpenCV can not write AVIF images, so a lossless PNG is encoded with `heif-enc` instead.
+    fn encode_avif(img: &Mat, quality: i32) -> Result<Vec<u8>, String> {
+        let temp_dir = tempdir::TempDir::new("fotoboek_avif").map_err(|err| err.to_string())?;
+        let png_path = temp_dir.path().join("preview.png");
+        let avif_path = temp_dir.path().join("preview.avif");
+        let png_bytes = encode(img, ".png", imgcodecs::IMWRITE_PNG_COMPRESSION, 1)?;
+        std::fs::write(&png_path, png_bytes).map_err(|err| err.to_string())?;
+
+        let output = Command::new("heif-enc")
+            .args(&["--avif", "--quality", &quality.to_string(), "--output"])
+            .arg(&avif_path)
+            .arg(&png_path)
+            .output()
+            .map_err(|err| format!("Failed to run heif-enc: {}", err))?;
+        if !output.status.success() {
+            return Err(format!(
+                "heif-enc failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        std::fs::read(&avif_path).map_err(|err| err.to_string())
+    }
+
     fn to_scale_factor(preview_size: &PreviewSize, image_size: Size) -> f64 {
         fn max(a: f64, b: f64) -> f64 {
             if a > b {
@@ -119,17 +364,17 @@ mod image {
             }
         }
 
-        match preview_size {
-            PreviewSize::Large => {
-                let target_max_pixels = 2000 as f64;
+        match preview_size.fit {
+            PreviewFit::Contain => {
+                let target_max_pixels = preview_size.pixels as f64;
                 let min_factor = min(
                     target_max_pixels / image_size.width as f64,
                     target_max_pixels / image_size.height as f64,
                 );
                 min(min_factor, 1.)
             }
-            PreviewSize::Small => {
-                let target_min_pixels = 200 as f64;
+            PreviewFit::Cover => {
+                let target_min_pixels = preview_size.pixels as f64;
                 let max_factor = max(
                     target_min_pixels / image_size.width as f64,
                     target_min_pixels / image_size.height as f64,
@@ -143,10 +388,92 @@ mod image {
     mod tests {
         use super::*;
 
+        fn large() -> PreviewSize {
+            "large:contain:2000:webp:85".parse().unwrap()
+        }
+
+        fn small() -> PreviewSize {
+            "small:cover:200:webp:85".parse().unwrap()
+        }
+
+        fn config(file_storage_path: &str) -> FotoboekConfig {
+            FotoboekConfig {
+                media_source_path: "".to_string(),
+                file_storage_path: file_storage_path.to_string(),
+                webapp_files_path: "".to_string(),
+                worker_pools: vec![],
+                worker_idle_interval_sec: 60,
+                task_lock_timeout_sec: 60,
+                task_max_attempts: 5,
+                task_retry_backoff_sec: 60,
+                fs_watcher_enabled: false,
+                scan_on_startup: false,
+                scan_interval_hours: 0,
+                similarity_max_distance: 0,
+                image_extensions: vec![],
+                raw_extensions: vec![],
+                video_extensions: vec![],
+                preview_sizes: vec![],
+                preview_on_demand_max_concurrent: 0,
+            }
+        }
+
+        /// Generates a JPEG and a WebP preview of a 16x16 fixture and checks, both are scaled
+        /// down to 8x8.
+        fn assert_previews_generated(file_name: &str) {
+            let temp_dir = tempdir::TempDir::new("preview_unittest").unwrap();
+            let config = config(temp_dir.path().to_str().unwrap());
+            let abs_path = format!(
+                "{}/tests/fixtures/{}",
+                env!("CARGO_MANIFEST_DIR"),
+                file_name
+            );
+            let file_hash = "0".repeat(64);
+            let jpeg: PreviewSize = "small:cover:8:jpeg:85".parse().unwrap();
+            let webp: PreviewSize = "medium:contain:8:webp:85".parse().unwrap();
+
+            run_task(&config, &abs_path, &file_hash, Some(1), &[&jpeg, &webp]).unwrap();
+
+            for preview_size in &[jpeg, webp] {
+                assert!(fs::preview_exists(&config, &file_hash, preview_size));
+                let preview = imgcodecs::imread(
+                    &fs::file_preview_path(&config, &file_hash, preview_size),
+                    imgcodecs::IMREAD_COLOR,
+                )
+                .unwrap();
+                assert_eq!((8, 8), (preview.cols(), preview.rows()));
+            }
+        }
+
+        #[test]
+        fn previews_of_png_images() {
+            assert_previews_generated("exif.png");
+        }
+
+        #[test]
+        fn previews_of_webp_images() {
+            assert_previews_generated("exif.webp");
+        }
+
+        #[test]
+        fn previews_of_tiff_images() {
+            assert_previews_generated("exif.tif");
+        }
+
+        #[test]
+        fn previews_of_heif_images() {
+            assert_previews_generated("exif.heic");
+        }
+
+        #[test]
+        fn previews_of_gif_images() {
+            assert_previews_generated("no_exif.gif");
+        }
+
         #[test]
         fn preview_size_larger_image_to_large_preview() {
             let scale_factor_horizontal = to_scale_factor(
-                &PreviewSize::Large,
+                &large(),
                 Size {
                     width: 3000,
                     height: 1000,
@@ -155,7 +482,7 @@ mod image {
             assert_eq!(2. / 3., scale_factor_horizontal);
 
             let scale_factor_vertical = to_scale_factor(
-                &PreviewSize::Large,
+                &large(),
                 Size {
                     width: 2000,
                     height: 4000,
@@ -164,7 +491,7 @@ mod image {
             assert_eq!(0.5, scale_factor_vertical);
 
             let scale_factor_quadratic = to_scale_factor(
-                &PreviewSize::Large,
+                &large(),
                 Size {
                     width: 4000,
                     height: 4000,
@@ -176,7 +503,7 @@ mod image {
         #[test]
         fn preview_size_smaller_image_to_large_preview() {
             let scale_factor_horizontal = to_scale_factor(
-                &PreviewSize::Large,
+                &large(),
                 Size {
                     width: 300,
                     height: 100,
@@ -185,7 +512,7 @@ mod image {
             assert_eq!(1., scale_factor_horizontal);
 
             let scale_factor_vertical = to_scale_factor(
-                &PreviewSize::Large,
+                &large(),
                 Size {
                     width: 200,
                     height: 400,
@@ -194,7 +521,7 @@ mod image {
             assert_eq!(1., scale_factor_vertical);
 
             let scale_factor_quadratic = to_scale_factor(
-                &PreviewSize::Large,
+                &large(),
                 Size {
                     width: 400,
                     height: 400,
@@ -206,7 +533,7 @@ mod image {
         #[test]
         fn preview_size_larger_image_to_small_preview() {
             let scale_factor_horizontal = to_scale_factor(
-                &PreviewSize::Small,
+                &small(),
                 Size {
                     width: 2000,
                     height: 1000,
@@ -215,7 +542,7 @@ mod image {
             assert_eq!(0.2, scale_factor_horizontal);
 
             let scale_factor_vertical = to_scale_factor(
-                &PreviewSize::Small,
+                &small(),
                 Size {
                     width: 1000,
                     height: 2000,
@@ -224,7 +551,7 @@ mod image {
             assert_eq!(0.2, scale_factor_vertical);
 
             let scale_factor_quadratic = to_scale_factor(
-                &PreviewSize::Small,
+                &small(),
                 Size {
                     width: 2000,
                     height: 2000,
@@ -236,7 +563,7 @@ mod image {
         #[test]
         fn preview_size_smaller_image_to_small_preview() {
             let scale_factor_horizontal = to_scale_factor(
-                &PreviewSize::Small,
+                &small(),
                 Size {
                     width: 400,
                     height: 100,
@@ -245,7 +572,7 @@ mod image {
             assert_eq!(1., scale_factor_horizontal);
 
             let scale_factor_vertical = to_scale_factor(
-                &PreviewSize::Small,
+                &small(),
                 Size {
                     width: 100,
                     height: 600,
@@ -254,7 +581,7 @@ mod image {
             assert_eq!(1., scale_factor_vertical);
 
             let scale_factor_quadratic = to_scale_factor(
-                &PreviewSize::Small,
+                &small(),
                 Size {
                     width: 150,
                     height: 150,
@@ -266,40 +593,323 @@ mod image {
 }
 
 mod video {
-    use log::warn;
+    use log::debug;
+    use opencv::core::{self, Rect, Size, Vector};
     use opencv::prelude::*;
-    use opencv::videoio::{VideoCapture, CAP_FFMPEG};
+    use opencv::videoio::{
+        VideoCapture, CAP_FFMPEG, CAP_PROP_FPS, CAP_PROP_FRAME_COUNT, CAP_PROP_POS_FRAMES,
+    };
+    use opencv::{imgcodecs, imgproc};
 
     use persistance::fs;
     use shared::models::{FotoboekConfig, PreviewSize};
 
     use crate::modules::preview::image;
 
+    /// Positions, relative to the duration, of the frames that are considered as poster frame.
+    const POSTER_FRAME_POSITIONS: [f64; 5] = [0.1, 0.25, 0.4, 0.55, 0.7];
+    /// Frames with a lower mean brightness (0-255) are considered black, e.g. during fade-ins.
+    const MIN_POSTER_FRAME_BRIGHTNESS: f64 = 20.;
+    const SPRITE_TILE_SIZE: i32 = 160;
+    const SPRITE_QUALITY: i32 = 75;
+
+    #[derive(Clone, Copy, Debug)]
+    struct FrameScore {
+        /// Mean brightness (0-255)
+        brightness: f64,
+        /// Variance of the Laplacian, which is higher for sharper frames
+        sharpness: f64,
+    }
+
+    /// Generates the previews and the sprite sheet of the video. `duration` is the duration in
+    /// seconds of the metadata, it is needed for videos that do not store their number of frames.
     pub fn run_task(
         config: &FotoboekConfig,
         abs_path: &String,
         file_hash: &String,
+        duration: Option<i32>,
+        preview_sizes: &[&PreviewSize],
+        with_sprite: bool,
     ) -> Result<(), String> {
-        let mut cap =
-            VideoCapture::from_file(abs_path, CAP_FFMPEG).expect("Failed to open video file");
+        let frames = VideoFrames::open(abs_path, duration)?;
+
+        if !preview_sizes.is_empty() {
+            let frame = read_poster_frame(&frames)?
+                .ok_or_else(|| format!("Could not read any frame of video {}", abs_path))?;
+            for preview_size in preview_sizes {
+                let resized = image::resize_by_cv_mat(&frame, preview_size)?;
+                fs::store_preview(config, file_hash, preview_size, &resized)?;
+            }
+        }
 
-        let mut frame = Mat::default();
-        if cap.read(&mut frame).unwrap_or(false) {
-            let resized_small =
-                image::resize_by_cv_mat(&frame, &PreviewSize::Small).expect("Resize failed");
-            fs::store_preview(config, file_hash, &PreviewSize::Small, &resized_small)
-                .expect("Store video small preview failed");
-
-            let resized_large =
-                image::resize_by_cv_mat(&frame, &PreviewSize::Large).expect("Resize failed");
-            fs::store_preview(config, file_hash, &PreviewSize::Large, &resized_large)
-                .expect("Store video large preview failed");
-        } else {
-            warn!(
-                "Could not read video frames, skipping preview generation for: {}",
-                abs_path
-            );
+        if with_sprite {
+            let sprite = create_sprite(&frames)?
+                .ok_or_else(|| format!("Could not read the frames of video {}", abs_path))?;
+            fs::store_video_sprite(config, file_hash, &sprite)?;
         }
         Ok(())
     }
+
+    /// Reads frames by their position relative to the duration. Frames of videos that store their
+    /// number of frames are seeked. Some containers do not, and are often not seekable reliably
+    /// either, so their number of frames is estimated from the duration, or counted if the duration
+    /// is unknown as well, and the frames are read one after the other.
+    struct VideoFrames {
+        abs_path: String,
+        frame_count: f64,
+        is_seekable: bool,
+    }
+
+    impl VideoFrames {
+        fn open(abs_path: &str, duration: Option<i32>) -> Result<VideoFrames, String> {
+            let cap = open_capture(abs_path)?;
+            let stored_frame_count = cap.get(CAP_PROP_FRAME_COUNT).unwrap_or(0.);
+            let (frame_count, is_seekable) = if stored_frame_count >= 1. {
+                (stored_frame_count, true)
+            } else {
+                let frame_rate = cap.get(CAP_PROP_FPS).unwrap_or(0.);
+                let frame_count = match duration {
+                    Some(duration) if duration > 0 && frame_rate > 0. => {
+                        duration as f64 * frame_rate
+                    }
+                    _ => count_frames(abs_path)?,
+                };
+                debug!(
+                    "Number of frames of {} unknown, reading {} frames sequentially",
+                    abs_path, frame_count
+                );
+                (frame_count, false)
+            };
+            Ok(VideoFrames {
+                abs_path: abs_path.to_string(),
+                frame_count,
+                is_seekable,
+            })
+        }
+
+        /// Reads the frames at the given positions (0-1), which must be ascending. Frames that can
+        /// not be read are `None`.
+        fn read_at(&self, positions: &[f64]) -> Result<Vec<Option<Mat>>, String> {
+            let mut cap = open_capture(&self.abs_path)?;
+            let indices = positions
+                .iter()
+                .map(|position| (self.frame_count * position).floor() as usize);
+            if self.is_seekable {
+                return Ok(indices
+                    .map(|index| read_frame_at(&mut cap, index as f64))
+                    .collect());
+            }
+
+            let mut next_index = 0;
+            let mut frames = Vec::with_capacity(positions.len());
+            for index in indices {
+                while next_index < index && cap.grab().unwrap_or(false) {
+                    next_index += 1;
+                }
+                if next_index == index {
+                    frames.push(read_frame(&mut cap));
+                    next_index += 1;
+                } else {
+                    frames.push(None);
+                }
+            }
+            Ok(frames)
+        }
+    }
+
+    fn open_capture(abs_path: &str) -> Result<VideoCapture, String> {
+        VideoCapture::from_file(abs_path, CAP_FFMPEG)
+            .map_err(|err| format!("Failed to open video file: {}", err))
+    }
+
+    fn count_frames(abs_path: &str) -> Result<f64, String> {
+        let mut cap = open_capture(abs_path)?;
+        let mut frame_count = 0.;
+        while cap.grab().unwrap_or(false) {
+            frame_count += 1.;
+        }
+        Ok(frame_count)
+    }
+
+    /// Picks the sharpest of several frames that are not black. The first frame is used if none of
+    /// them can be read.
+    fn read_poster_frame(frames: &VideoFrames) -> Result<Option<Mat>, String> {
+        let mut candidates = Vec::new();
+        for frame in frames
+            .read_at(&POSTER_FRAME_POSITIONS)?
+            .into_iter()
+            .flatten()
+        {
+            let score = frame_score(&frame)?;
+            candidates.push((frame, score));
+        }
+        if candidates.is_empty() {
+            return Ok(frames.read_at(&[0.])?.pop().flatten());
+        }
+
+        let scores: Vec<FrameScore> = candidates.iter().map(|(_, score)| *score).collect();
+        let index = choose_poster_frame(&scores).unwrap_or(0);
+        Ok(Some(candidates.swap_remove(index).0))
+    }
+
+    /// Returns the index of the sharpest frame that is not black, or of the brightest frame if all
+    /// frames are black.
+    fn choose_poster_frame(scores: &[FrameScore]) -> Option<usize> {
+        let indices = 0..scores.len();
+        let bright_indices: Vec<usize> = indices
+            .clone()
+            .filter(|index| scores[*index].brightness >= MIN_POSTER_FRAME_BRIGHTNESS)
+            .collect();
+        if bright_indices.is_empty() {
+            index_of_max(indices, |index| scores[index].brightness)
+        } else {
+            index_of_max(bright_indices.into_iter(), |index| scores[index].sharpness)
+        }
+    }
+
+    fn index_of_max<I, F>(indices: I, key: F) -> Option<usize>
+    where
+        I: Iterator<Item = usize>,
+        F: Fn(usize) -> f64,
+    {
+        indices.max_by(|a, b| {
+            key(*a)
+                .partial_cmp(&key(*b))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+    }
+
+    fn frame_score(frame: &Mat) -> Result<FrameScore, String> {
+        let mut gray = Mat::default();
+        imgproc::cvt_color(frame, &mut gray, imgproc::COLOR_BGR2GRAY, 0)
+            .map_err(|err| err.to_string())?;
+        let (brightness, _) = mean_and_std_dev(&gray)?;
+
+        let mut laplacian = Mat::default();
+        imgproc::laplacian(
+            &gray,
+            &mut laplacian,
+            core::CV_64F,
+            1,
+            1.,
+            0.,
+            core::BORDER_DEFAULT,
+        )
+        .map_err(|err| err.to_string())?;
+        let (_, std_dev) = mean_and_std_dev(&laplacian)?;
+
+        Ok(FrameScore {
+            brightness,
+            sharpness: std_dev * std_dev,
+        })
+    }
+
+    fn mean_and_std_dev(img: &Mat) -> Result<(f64, f64), String> {
+        let mut mean = Vector::<f64>::new();
+        let mut std_dev = Vector::<f64>::new();
+        core::mean_std_dev(img, &mut mean, &mut std_dev, &Mat::default())
+            .map_err(|err| err.to_string())?;
+        Ok((
+            mean.to_vec().first().copied().unwrap_or(0.),
+            std_dev.to_vec().first().copied().unwrap_or(0.),
+        ))
+    }
+
+    /// Creates a sprite sheet of square frames, evenly distributed over the video, side by side.
+    /// Frames that can not be read are replaced by the previous frame.
+    fn create_sprite(frames: &VideoFrames) -> Result<Option<Vec<u8>>, String> {
+        let positions: Vec<f64> = (0..fs::VIDEO_SPRITE_FRAMES)
+            .map(|index| (index as f64 + 0.5) / fs::VIDEO_SPRITE_FRAMES as f64)
+            .collect();
+
+        let mut tiles = Vector::<Mat>::new();
+        for (index, frame) in frames.read_at(&positions)?.into_iter().enumerate() {
+            let tile = match frame {
+                Some(frame) => to_tile(&frame)?,
+                None if index > 0 => tiles.get(index - 1).map_err(|err| err.to_string())?,
+                None => return Ok(None),
+            };
+            tiles.push(tile);
+        }
+
+        let mut sprite = Mat::default();
+        core::hconcat(&tiles, &mut sprite).map_err(|err| err.to_string())?;
+        image::encode(
+            &sprite,
+            ".webp",
+            imgcodecs::IMWRITE_WEBP_QUALITY,
+            SPRITE_QUALITY,
+        )
+        .map(Some)
+    }
+
+    /// Crops the center square of the frame and scales it to the tile size.
+    fn to_tile(frame: &Mat) -> Result<Mat, String> {
+        let size = frame.size().map_err(|err| err.to_string())?;
+        let edge = size.width.min(size.height);
+        let square = Rect::new(
+            (size.width - edge) / 2,
+            (size.height - edge) / 2,
+            edge,
+            edge,
+        );
+        let cropped = Mat::roi(frame, square).map_err(|err| err.to_string())?;
+
+        let mut tile = Mat::default();
+        imgproc::resize(
+            &cropped,
+            &mut tile,
+            Size::new(SPRITE_TILE_SIZE, SPRITE_TILE_SIZE),
+            0.,
+            0.,
+            imgproc::INTER_AREA,
+        )
+        .map_err(|err| err.to_string())?;
+        Ok(tile)
+    }
+
+    fn read_frame_at(cap: &mut VideoCapture, frame_index: f64) -> Option<Mat> {
+        cap.set(CAP_PROP_POS_FRAMES, frame_index.floor()).ok()?;
+        read_frame(cap)
+    }
+
+    fn read_frame(cap: &mut VideoCapture) -> Option<Mat> {
+        let mut frame = Mat::default();
+        match cap.read(&mut frame) {
+            Ok(true) if !frame.empty().unwrap_or(true) => Some(frame),
+            _ => None,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn scores(values: &[(f64, f64)]) -> Vec<FrameScore> {
+            values
+                .iter()
+                .map(|(brightness, sharpness)| FrameScore {
+                    brightness: *brightness,
+                    sharpness: *sharpness,
+                })
+                .collect()
+        }
+
+        #[test]
+        fn sharpest_bright_frame_is_poster_frame() {
+            let scores = scores(&[(10., 900.), (120., 50.), (90., 300.), (200., 100.)]);
+            assert_eq!(Some(2), choose_poster_frame(&scores));
+        }
+
+        #[test]
+        fn brightest_frame_is_poster_frame_if_all_are_black() {
+            let scores = scores(&[(2., 900.), (15., 50.), (8., 300.)]);
+            assert_eq!(Some(1), choose_poster_frame(&scores));
+        }
+
+        #[test]
+        fn no_poster_frame_without_frames() {
+            assert_eq!(None, choose_poster_frame(&[]));
+        }
+    }
 }
@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+const COMPRESSION_OLD_JPEG: u32 = 6;
+const COMPRESSION_JPEG: u32 = 7;
+
+/// Upper bound of IFDs to visit, protects against malformed files.
+const MAX_IFDS: usize = 64;
+
+/// Returns the largest JPEG preview embedded in a TIFF based RAW file (CR2, NEF, ARW, DNG). Only
+/// baseline and progressive JPEGs are considered, lossless JPEGs hold the raw sensor data.
+pub fn find_embedded_preview(contents: &[u8]) -> Option<&[u8]> {
+    let tiff = Tiff::parse(contents)?;
+
+    let mut previews: Vec<&[u8]> = Vec::new();
+    let mut visited_offsets = HashSet::new();
+    let mut ifd_offsets = vec![tiff.read_u32(4)? as usize];
+    while let Some(ifd_offset) = ifd_offsets.pop() {
+        if ifd_offset == 0 || visited_offsets.len() >= MAX_IFDS {
+            continue;
+        }
+        if !visited_offsets.insert(ifd_offset) {
+            continue;
+        }
+        if let Some(ifd) = tiff.read_ifd(ifd_offset) {
+            previews.extend(ifd.preview_ranges().filter_map(|(offset, length)| {
+                contents
+                    .get(offset..offset.checked_add(length)?)
+                    .filter(|data| is_displayable_jpeg(data))
+            }));
+            ifd_offsets.extend(ifd.sub_ifd_offsets);
+            ifd_offsets.push(ifd.next_ifd_offset);
+        }
+    }
+
+    previews.into_iter().max_by_key(|preview| preview.len())
+}
+
+struct Tiff<'a> {
+    contents: &'a [u8],
+    big_endian: bool,
+}
+
+#[derive(Default)]
+struct Ifd {
+    compression: Option<u32>,
+    strip_offsets: Vec<usize>,
+    strip_byte_counts: Vec<usize>,
+    jpeg_offset: Option<usize>,
+    jpeg_length: Option<usize>,
+    sub_ifd_offsets: Vec<usize>,
+    next_ifd_offset: usize,
+}
+
+impl Ifd {
+    fn preview_ranges(&self) -> impl Iterator<Item = (usize, usize)> {
+        let jpeg_range = self.jpeg_offset.zip(self.jpeg_length);
+        let is_jpeg_compressed = matches!(
+            self.compression,
+            Some(COMPRESSION_OLD_JPEG) | Some(COMPRESSION_JPEG)
+        );
+        let strip_range = match (&self.strip_offsets[..], &self.strip_byte_counts[..]) {
+            ([offset], [length]) if is_jpeg_compressed => Some((*offset, *length)),
+            _ => None,
+        };
+        jpeg_range.into_iter().chain(strip_range)
+    }
+}
+
+impl<'a> Tiff<'a> {
+    fn parse(contents: &'a [u8]) -> Option<Tiff<'a>> {
+        let big_endian = match contents.get(0..4)? {
+            b"II*\0" => false,
+            b"MM\0*" => true,
+            _ => return None,
+        };
+        Some(Tiff {
+            contents,
+            big_endian,
+        })
+    }
+
+    fn read_u16(&self, offset: usize) -> Option<u16> {
+        let bytes = [*self.contents.get(offset)?, *self.contents.get(offset + 1)?];
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_u32(&self, offset: usize) -> Option<u32> {
+        let bytes = [
+            *self.contents.get(offset)?,
+            *self.contents.get(offset + 1)?,
+            *self.contents.get(offset + 2)?,
+            *self.contents.get(offset + 3)?,
+        ];
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_ifd(&self, offset: usize) -> Option<Ifd> {
+        let entry_count = self.read_u16(offset)? as usize;
+        let mut ifd = Ifd::default();
+        for index in 0..entry_count {
+            let entry_offset = offset + 2 + index * 12;
+            let tag = self.read_u16(entry_offset)?;
+            let values = self.read_values(entry_offset)?;
+            match tag {
+                TAG_COMPRESSION => ifd.compression = values.first().copied(),
+                TAG_STRIP_OFFSETS => ifd.strip_offsets = to_usizes(values),
+                TAG_STRIP_BYTE_COUNTS => ifd.strip_byte_counts = to_usizes(values),
+                TAG_SUB_IFDS => ifd.sub_ifd_offsets = to_usizes(values),
+                TAG_JPEG_OFFSET => ifd.jpeg_offset = values.first().map(|it| *it as usize),
+                TAG_JPEG_LENGTH => ifd.jpeg_length = values.first().map(|it| *it as usize),
+                _ => (),
+            }
+        }
+        ifd.next_ifd_offset = self.read_u32(offset + 2 + entry_count * 12)? as usize;
+        Some(ifd)
+    }
+
+    /// Reads the values of SHORT, LONG and IFD entries, which are stored in the entry itself if
+    /// they fit into 4 bytes. Values of all other types are ignored.
+    fn read_values(&self, entry_offset: usize) -> Option<Vec<u32>> {
+        let field_type = self.read_u16(entry_offset + 2)?;
+        let count = self.read_u32(entry_offset + 4)? as usize;
+        let value_size = match field_type {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Some(vec![]),
+        };
+        if count > MAX_IFDS {
+            return Some(vec![]);
+        }
+
+        let values_offset = if count * value_size <= 4 {
+            entry_offset + 8
+        } else {
+            self.read_u32(entry_offset + 8)? as usize
+        };
+        (0..count)
+            .map(|index| {
+                let value_offset = values_offset + index * value_size;
+                if value_size == 2 {
+                    self.read_u16(value_offset).map(|value| value as u32)
+                } else {
+                    self.read_u32(value_offset)
+                }
+            })
+            .collect()
+    }
+}
+
+fn to_usizes(values: Vec<u32>) -> Vec<usize> {
+    values.into_iter().map(|value| value as usize).collect()
+}
+
+/// Returns true if the data is a baseline or progressive JPEG, by looking for the start of frame
+/// marker before the start of scan.
+fn is_displayable_jpeg(data: &[u8]) -> bool {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
+
+    let mut offset = 2;
+    while let (Some(0xFF), Some(marker)) = (data.get(offset), data.get(offset + 1)) {
+        match marker {
+            0xC0 | 0xC1 | 0xC2 => return true,
+            0xC3 | 0xDA | 0xD9 => return false,
+            0xFF => offset += 1,
+            0x01 | 0xD0..=0xD7 => offset += 2,
+            _ => match (data.get(offset + 2), data.get(offset + 3)) {
+                (Some(high), Some(low)) => offset += 2 + u16::from_be_bytes([*high, *low]) as usize,
+                _ => return false,
+            },
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_displayable_preview_found() {
+        let contents = include_bytes!("../../tests/fixtures/raw_preview.dng");
+        let preview = find_embedded_preview(contents).unwrap();
+        assert!(is_displayable_jpeg(preview));
+        assert_eq!(100, preview.len());
+    }
+
+    #[test]
+    fn no_preview_in_other_files() {
+        let contents = include_bytes!("../../tests/fixtures/exif.tif");
+        assert_eq!(None, find_embedded_preview(contents));
+
+        let contents = include_bytes!("../../tests/fixtures/no_exif.gif");
+        assert_eq!(None, find_embedded_preview(contents));
+    }
+
+    #[test]
+    fn no_preview_in_truncated_file() {
+        let contents = include_bytes!("../../tests/fixtures/raw_preview.dng");
+        assert_eq!(None, find_embedded_preview(&contents[..64]));
+    }
+
+    #[test]
+    fn lossless_jpeg_is_not_displayable() {
+        assert!(is_displayable_jpeg(&[0xFF, 0xD8, 0xFF, 0xC0, 0x00, 0x02]));
+        assert!(is_displayable_jpeg(&[
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC2
+        ]));
+        assert!(!is_displayable_jpeg(&[0xFF, 0xD8, 0xFF, 0xC3, 0x00, 0x02]));
+        assert!(!is_displayable_jpeg(&[0x00, 0xD8, 0xFF, 0xC0, 0x00, 0x02]));
+    }
+}
@@ -5,22 +5,19 @@ use shared::models::FotoboekConfig;
 use shared::path_utils::rel_to_abs;
 use std::process::Command;
 use std::str::from_utf8;
+use tokio::task;
+
+use crate::modules::metadata;
 
 pub const MODULE_ID: &str = "transcode";
 
 pub async fn create_tasks_on_new_file(db: &FotoboekDatabase, file: &File) -> Result<(), String> {
     // Only videos can be transcoded
     if file.file_type == "VIDEO" {
-        Task {
-            id: None,
-            file_id: file.id.unwrap(),
-            module: MODULE_ID.into(),
-            priority: 300,
-            max_worker_id: 0,
-            work_started_at: chrono::NaiveDateTime::from_timestamp(0, 0),
-        }
-        .insert(db)
-        .await?;
+        Task::new(file.id.unwrap(), MODULE_ID, 300)
+            .depends_on(metadata::MODULE_ID)
+            .insert(db)
+            .await?;
     }
 
     Ok(())
@@ -31,23 +28,53 @@ pub async fn run_task(
     config: &FotoboekConfig,
     task: &Task,
 ) -> Result<(), String> {
-    let metadata = FileMetadata::by_file_id(db, task.file_id).await.ok_or(
-        "File metadata not found, very likely because the metadata task did not finish yet"
-            .to_string(),
-    )?;
+    let metadata = FileMetadata::by_file_id(db, task.file_id)
+        .await
+        .ok_or("File metadata not found".to_string())?;
     let file = File::by_id(db, task.file_id).await?;
+    if fs::video_exists(config, &metadata.file_hash) {
+        debug!(
+            "Transcoded video of {} already exists, skipping",
+            file.rel_path
+        );
+        return Ok(());
+    }
     let abs_source_path = rel_to_abs(config, &file.rel_path);
     let abs_target_path = fs::video_path(config, &metadata.file_hash);
 
     // Make sure, the directory exists
     std::fs::create_dir_all(fs::video_dir_path(config, &metadata.file_hash)).unwrap();
 
-    execute_command(abs_source_path, abs_target_path, config.num_worker_threads)
+    // Transcode into a temporary file first, so an interrupted transcode never leaves a partial
+    // video behind that would be mistaken for a finished one
+    let abs_partial_path = format!("{}.partial", abs_target_path);
+    // Only drop the audio if the streams are known, metadata of older versions lacks the codecs
+    let has_audio = metadata.video_codec.is_none() || metadata.audio_codec.is_some();
+    // ffmpeg runs for minutes, it must not block the async runtime that renews the task lock
+    let threads = config.num_workers();
+    let partial_path = abs_partial_path.clone();
+    task::spawn_blocking(move || {
+        execute_command(abs_source_path, partial_path, threads, has_audio)
+    })
+    .await
+    .map_err(|err| err.to_string())??;
+    std::fs::rename(&abs_partial_path, &abs_target_path).map_err(|err| err.to_string())
 }
 
-fn execute_command(source_path: String, target_path: String, threads: usize) -> Result<(), String> {
+fn execute_command(
+    source_path: String,
+    target_path: String,
+    threads: usize,
+    has_audio: bool,
+) -> Result<(), String> {
     // Recommodations from http://wiki.webmproject.org/ffmpeg/vp9-encoding-guide
 
+    // The statistics of the first pass are written to the working directory by default, where
+    // concurrent transcodes would overwrite each other's
+    let temp_dir = tempdir::TempDir::new("fotoboek_transcode").map_err(|err| err.to_string())?;
+    let passlogfile = temp_dir.path().join("ffmpeg2pass");
+    let passlogfile = passlogfile.to_str().ok_or("Invalid temp dir path")?;
+
     debug!("Starting transcode video {}, pass 1...", source_path);
     let output = Command::new("ffmpeg")
         .args(vec![
@@ -57,6 +84,8 @@ fn execute_command(source_path: String, target_path: String, threads: usize) ->
             "libvpx-vp9",
             "-pass",
             "1",
+            "-passlogfile",
+            passlogfile,
             "-b:v",
             "1000K",
             "-threads",
@@ -81,6 +110,11 @@ fn execute_command(source_path: String, target_path: String, threads: usize) ->
     }
 
     debug!("Transcode pass 1 done, starting with pass 2...");
+    let audio_args = if has_audio {
+        vec!["-c:a", "libopus", "-b:a", "64k"]
+    } else {
+        vec!["-an"]
+    };
     let output = Command::new("ffmpeg")
         .args(vec![
             "-i",
@@ -89,6 +123,8 @@ fn execute_command(source_path: String, target_path: String, threads: usize) ->
             "libvpx-vp9",
             "-pass",
             "2",
+            "-passlogfile",
+            passlogfile,
             "-b:v",
             "1000K",
             "-threads",
@@ -103,15 +139,9 @@ fn execute_command(source_path: String, target_path: String, threads: usize) ->
             "1",
             "-lag-in-frames",
             "25",
-            "-c:a",
-            "libopus",
-            "-b:a",
-            "64k",
-            "-f",
-            "webm",
-            "-y",
-            target_path.as_str(),
         ])
+        .args(audio_args)
+        .args(vec!["-f", "webm", "-y", target_path.as_str()])
         .output()
         .map_err(|err| err.to_string())?;
 
@@ -0,0 +1,145 @@
+use log::{error, info, trace};
+use persistance::models::Scan;
+use persistance::FotoboekDatabase;
+use shared::models::FotoboekConfig;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::task;
+use tokio::time::{sleep, Duration};
+
+use crate::{source_images, source_videos};
+
+pub const TRIGGER_MANUAL: &str = "manual";
+pub const TRIGGER_STARTUP: &str = "startup";
+pub const TRIGGER_INTERVAL: &str = "interval";
+pub const TRIGGER_WATCHER: &str = "watcher";
+
+/// Set while a scan runs, scans of the same files must never run concurrently.
+static SCAN_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Marks the scan as running until it is dropped, also if the scan panics.
+struct RunningScanGuard;
+
+impl RunningScanGuard {
+    /// Returns None if another scan is already running.
+    fn acquire() -> Option<RunningScanGuard> {
+        SCAN_RUNNING
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .ok()
+            .map(|_| RunningScanGuard)
+    }
+}
+
+impl Drop for RunningScanGuard {
+    fn drop(&mut self) {
+        SCAN_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Registers a new scan and runs it in the background. The returned scan can be used to poll the
+/// progress of the scan. If a scan is already running, that scan is returned instead.
+pub async fn start_scan(
+    db: FotoboekDatabase,
+    config: &FotoboekConfig,
+    triggered_by: &str,
+) -> Result<Scan, String> {
+    let guard = match RunningScanGuard::acquire() {
+        Some(guard) => guard,
+        None => {
+            return Scan::latest_running(&db)
+                .await
+                .ok_or_else(|| "Another scan is already running".to_string())
+        }
+    };
+    let scan = Scan::start(triggered_by).insert(&db).await?;
+
+    let config_copy = config.clone();
+    let scan_copy = scan.clone();
+    task::spawn(async move {
+        run_scan(&db, &config_copy, scan_copy).await;
+        drop(guard);
+    });
+
+    Ok(scan)
+}
+
+/// Registers a new scan and runs it to completion. Fails if another scan is already running.
+pub async fn scan(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    triggered_by: &str,
+) -> Result<Scan, String> {
+    let _guard =
+        RunningScanGuard::acquire().ok_or_else(|| "Another scan is already running".to_string())?;
+    let scan = Scan::start(triggered_by).insert(db).await?;
+    Ok(run_scan(db, config, scan).await)
+}
+
+/// Marks scans that are still running from a previous start of the application as failed, since
+/// they were interrupted. Must be called before any scan is started. Returns the number of failed
+/// scans.
+pub async fn fail_interrupted_scans(db: &FotoboekDatabase) -> Result<usize, String> {
+    Scan::fail_running(db, "Interrupted by a restart").await
+}
+
+/// Scans the media source path for images and videos and updates the database accordingly. The
+/// progress and result is recorded in the given scan.
+async fn run_scan(db: &FotoboekDatabase, config: &FotoboekConfig, mut scan: Scan) -> Scan {
+    info!("Starting {} scan {:?}", scan.triggered_by, scan.id);
+
+    let images = source_images::search_and_update_db(db, config).await;
+    scan.images_total = images.total_count as i32;
+    scan.images_added = images.added_count as i32;
+    scan.images_removed = images.removed_count as i32;
+    scan.images_modified = images.modified_count as i32;
+    scan.images_moved = images.moved_count as i32;
+    update_scan(db, &scan).await;
+
+    let videos = source_videos::search_and_update_db(db, config).await;
+    scan.videos_total = videos.total_count as i32;
+    scan.videos_added = videos.added_count as i32;
+    scan.videos_removed = videos.removed_count as i32;
+    scan.videos_modified = videos.modified_count as i32;
+    scan.videos_moved = videos.moved_count as i32;
+
+    let errors = [images.errors, videos.errors].concat();
+    if !errors.is_empty() {
+        scan.errors = Some(errors.join("\n"));
+    }
+    scan.finish();
+    update_scan(db, &scan).await;
+
+    info!("Finished scan {:?}", scan);
+    scan
+}
+
+async fn update_scan(db: &FotoboekDatabase, scan: &Scan) {
+    if let Err(err) = scan.update(db).await {
+        error!("Updating scan {:?} failed: {}", scan.id, err);
+    }
+}
+
+/// Runs a scan on startup and every `scan_interval_hours` afterwards, as configured.
+pub fn spawn_scheduler(db: FotoboekDatabase, config: &FotoboekConfig) {
+    let config_copy = config.clone();
+    task::spawn(async move {
+        if config_copy.scan_on_startup {
+            run_scheduled_scan(&db, &config_copy, TRIGGER_STARTUP).await;
+        }
+        if config_copy.scan_interval_hours == 0 {
+            trace!("Scan interval disabled, no further scans scheduled");
+            return;
+        }
+
+        let interval = Duration::from_secs(config_copy.scan_interval_hours as u64 * 60 * 60);
+        loop {
+            sleep(interval).await;
+            run_scheduled_scan(&db, &config_copy, TRIGGER_INTERVAL).await;
+        }
+    });
+}
+
+async fn run_scheduled_scan(db: &FotoboekDatabase, config: &FotoboekConfig, triggered_by: &str) {
+    if let Err(err) = scan(db, config, triggered_by).await {
+        error!("Failed to run {} scan: {}", triggered_by, err);
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+use futures::future;
+use log::{debug, error, warn};
+use persistance::models::{File, FileMetadata};
+use persistance::{fs, FotoboekDatabase};
+use sha2::{Digest, Sha256};
+use shared::models::FotoboekConfig;
+use shared::path_utils::{abs_pathbuf_to_rel, get_filename, rel_to_abs};
+
+/// Removes all registered files of the given type that are not part of the given source paths
+/// anymore. Returns the number of removed files and the errors of files that failed to be removed.
+/// Nothing is removed if the media source path looks unavailable, e.g. because it is not mounted.
+pub async fn remove_vanished_files(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    file_type: &str,
+    source_paths: &Vec<PathBuf>,
+) -> (usize, Vec<String>) {
+    let registered_files = File::by_file_type(db, file_type).await;
+    if let Some(reason) = source_unavailable_reason(
+        &config.media_source_path,
+        source_paths.len(),
+        registered_files.len(),
+    ) {
+        let err = format!("Not removing vanished {} files: {}", file_type, reason);
+        error!("{}", err);
+        return (0, vec![err]);
+    }
+
+    let source_rel_paths: HashSet<&str> = source_paths
+        .iter()
+        .map(|source_path| abs_pathbuf_to_rel(config, source_path))
+        .collect();
+    let vanished_files = registered_files
+        .into_iter()
+        .filter(|file| !source_rel_paths.contains(file.rel_path.as_str()))
+        .collect::<Vec<_>>();
+    let vanished_rel_paths = vanished_files
+        .iter()
+        .map(|file| file.rel_path.clone())
+        .collect::<Vec<_>>();
+    let remove_futures = vanished_files
+        .into_iter()
+        .map(|file| remove_file(db, config, file))
+        .collect::<Vec<_>>();
+    let remove_results = future::join_all(remove_futures).await;
+    let errors = vanished_rel_paths
+        .iter()
+        .zip(remove_results.iter())
+        .filter_map(|(rel_path, result)| {
+            result
+                .as_ref()
+                .err()
+                .map(|err| format!("Failed to remove file {}: {}", rel_path, err))
+        })
+        .collect::<Vec<_>>();
+    errors.iter().for_each(|err| warn!("{}", err));
+
+    (vanished_rel_paths.len() - errors.len(), errors)
+}
+
+/// An unmounted or unreadable media source path is indistinguishable from one whose files were all
+/// deleted, so no files are considered vanished if the source path is missing or none of the
+/// registered files were found.
+fn source_unavailable_reason(
+    media_source_path: &str,
+    source_count: usize,
+    registered_count: usize,
+) -> Option<String> {
+    if let Err(err) = std::fs::read_dir(media_source_path) {
+        return Some(format!(
+            "Media source path {} is not readable: {}",
+            media_source_path, err
+        ));
+    }
+    if source_count == 0 && registered_count > 0 {
+        return Some(format!(
+            "No files found in media source path {}, but {} are registered",
+            media_source_path, registered_count
+        ));
+    }
+    None
+}
+
+/// Detects which of the new paths are registered files that were moved away from a path that
+/// vanished, by comparing file size and content hash. Those files are updated in place to keep
+/// their id, metadata and previews. Returns the number of moved files and the remaining new paths.
+pub async fn update_moved_files<'a>(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    file_type: &str,
+    source_paths: &Vec<PathBuf>,
+    new_paths: Vec<&'a PathBuf>,
+) -> (usize, Vec<&'a PathBuf>) {
+    let source_rel_paths: HashSet<&str> = source_paths
+        .iter()
+        .map(|source_path| abs_pathbuf_to_rel(config, source_path))
+        .collect();
+    let vanished_files = File::by_file_type(db, file_type)
+        .await
+        .into_iter()
+        .filter(|file| !source_rel_paths.contains(file.rel_path.as_str()))
+        .collect::<Vec<_>>();
+    if vanished_files.is_empty() || new_paths.is_empty() {
+        return (0, new_paths);
+    }
+
+    let mut metadata_by_file_id: HashMap<i32, FileMetadata> = FileMetadata::all(db)
+        .await
+        .into_iter()
+        .map(|metadata| (metadata.file_id.unwrap(), metadata))
+        .collect();
+    let mut vanished_files_by_size: HashMap<i32, Vec<(File, String)>> = HashMap::new();
+    for file in vanished_files {
+        if let Some(metadata) = metadata_by_file_id.remove(&file.id.unwrap()) {
+            vanished_files_by_size
+                .entry(metadata.file_size_bytes)
+                .or_insert(Vec::new())
+                .push((file, metadata.file_hash));
+        }
+    }
+
+    let mut moved_count = 0;
+    let mut remaining_new_paths = Vec::new();
+    for new_path in new_paths {
+        let moved_file = take_moved_file(&mut vanished_files_by_size, new_path);
+        if let Some(file) = moved_file {
+            let rel_path = abs_pathbuf_to_rel(config, new_path);
+            match move_file(db, file, rel_path).await {
+                Ok(()) => {
+                    moved_count += 1;
+                    continue;
+                }
+                Err(err) => warn!("Failed to move file to {}: {}", rel_path, err),
+            }
+        }
+        remaining_new_paths.push(new_path);
+    }
+
+    (moved_count, remaining_new_paths)
+}
+
+/// Returns the vanished file with the same size and content as the file at the given path.
+fn take_moved_file(
+    vanished_files_by_size: &mut HashMap<i32, Vec<(File, String)>>,
+    abs_path: &PathBuf,
+) -> Option<File> {
+    let abs_path = abs_path.to_str().unwrap().to_string();
+    let (file_size_bytes, _) = crate::modules::get_file_size_and_modified_date(&abs_path).ok()?;
+    let candidates = vanished_files_by_size.get_mut(&file_size_bytes)?;
+
+    let file_hash = digest_file(&abs_path).ok()?;
+    let index = candidates
+        .iter()
+        .position(|(_, candidate_hash)| *candidate_hash == file_hash)?;
+    Some(candidates.remove(index).0)
+}
+
+/// Returns the SHA-256 hash of the file, without loading the whole file into memory.
+fn digest_file(abs_path: &str) -> io::Result<String> {
+    let mut reader = BufReader::new(std::fs::File::open(abs_path)?);
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Moves the file registered for the given relative path to the new relative path. If no file is
+/// registered for it, the path is treated as a directory and all files below it are moved.
+/// Returns the number of moved files.
+pub async fn try_move_path(
+    db: &FotoboekDatabase,
+    from_rel_path: &str,
+    to_rel_path: &str,
+) -> Result<usize, String> {
+    if let Some(file) = File::by_rel_path(db, from_rel_path).await {
+        move_file(db, file, to_rel_path).await?;
+        return Ok(1);
+    }
+
+    let from_dir_prefix = format!("{}/", from_rel_path.trim_end_matches('/'));
+    let to_dir_prefix = format!("{}/", to_rel_path.trim_end_matches('/'));
+    let files = File::by_rel_path_prefix(db, &from_dir_prefix).await;
+    let moved_count = files.len();
+    for file in files {
+        let rel_path = format!(
+            "{}{}",
+            to_dir_prefix,
+            &file.rel_path[from_dir_prefix.len()..]
+        );
+        move_file(db, file, &rel_path).await?;
+    }
+    Ok(moved_count)
+}
+
+async fn move_file(db: &FotoboekDatabase, mut file: File, rel_path: &str) -> Result<(), String> {
+    debug!("Moving file {} to {}", file.rel_path, rel_path);
+    file.file_name = get_filename(&PathBuf::from(rel_path));
+    file.rel_path = rel_path.to_string();
+    file.update(db).await
+}
+
+/// Removes the file registered for the given relative path. If no file is registered for it, the
+/// path is treated as a directory and all files below it are removed. Returns the number of
+/// removed files.
+pub async fn try_remove_path(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    rel_path: &str,
+) -> Result<usize, String> {
+    if let Some(file) = File::by_rel_path(db, rel_path).await {
+        remove_file(db, config, file).await?;
+        return Ok(1);
+    }
+
+    let dir_prefix = format!("{}/", rel_path.trim_end_matches('/'));
+    let mut removed_count = 0;
+    for file in File::by_rel_path_prefix(db, &dir_prefix).await {
+        remove_file(db, config, file).await?;
+        removed_count += 1;
+    }
+    Ok(removed_count)
+}
+
+/// Deletes the file from the database and garbage collects its previews and transcoded video.
+pub async fn remove_file(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    file: File,
+) -> Result<(), String> {
+    let metadata = FileMetadata::by_file_id(db, file.id.unwrap()).await;
+    file.delete(db).await?;
+
+    if let Some(metadata) = metadata {
+        delete_unreferenced_files(db, config, &metadata.file_hash).await?;
+    }
+    Ok(())
+}
+
+/// Deletes the previews and the transcoded video of the given file hash, unless another file with
+/// the same content still references them.
+pub async fn delete_unreferenced_files(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    file_hash: &String,
+) -> Result<(), String> {
+    if FileMetadata::count_by_file_hash(db, file_hash).await == 0 {
+        fs::delete_previews(config, file_hash)?;
+        fs::delete_video(config, file_hash)?;
+    }
+    Ok(())
+}
+
+/// Recreates the tasks of all registered files of the given type that changed on disk since their
+/// metadata was extracted. Returns the number of modified files and the errors of files that
+/// failed to be updated.
+pub async fn update_modified_files(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    file_type: &str,
+    source_paths: &Vec<PathBuf>,
+) -> (usize, Vec<String>) {
+    let source_rel_paths: HashSet<&str> = source_paths
+        .iter()
+        .map(|source_path| abs_pathbuf_to_rel(config, source_path))
+        .collect();
+    let metadata_by_file_id: HashMap<i32, FileMetadata> = FileMetadata::all(db)
+        .await
+        .into_iter()
+        .map(|metadata| (metadata.file_id.unwrap(), metadata))
+        .collect();
+    let modified_files = File::by_file_type(db, file_type)
+        .await
+        .into_iter()
+        .filter(|file| source_rel_paths.contains(file.rel_path.as_str()))
+        .filter(|file| {
+            metadata_by_file_id
+                .get(&file.id.unwrap())
+                .map(|metadata| is_modified(config, file, metadata))
+                .unwrap_or(false)
+        })
+        .collect::<Vec<_>>();
+
+    let mut errors = Vec::new();
+    for file in modified_files.iter() {
+        if let Err(err) = crate::modules::recreate_tasks(db, file).await {
+            errors.push(format!("Failed to update file {}: {}", file.rel_path, err));
+        }
+    }
+    errors.iter().for_each(|err| warn!("{}", err));
+
+    (modified_files.len() - errors.len(), errors)
+}
+
+/// Recreates the tasks of the file if it changed on disk since its metadata was extracted. Returns
+/// true if the file was modified.
+pub async fn update_if_modified(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    file: &File,
+) -> Result<bool, String> {
+    let modified = FileMetadata::by_file_id(db, file.id.unwrap())
+        .await
+        .map(|metadata| is_modified(config, file, &metadata))
+        .unwrap_or(false);
+    if modified {
+        crate::modules::recreate_tasks(db, file).await?;
+    }
+    Ok(modified)
+}
+
+/// Returns true if size or modification date of the file on disk do not match the metadata. For
+/// metadata extracted before the modification date was stored, only a modification date newer than
+/// the file date counts.
+fn is_modified(config: &FotoboekConfig, file: &File, metadata: &FileMetadata) -> bool {
+    match crate::modules::get_file_size_and_modified_date(&rel_to_abs(config, &file.rel_path)) {
+        Ok((file_size_bytes, modified_date)) => {
+            file_size_bytes != metadata.file_size_bytes
+                || match metadata.file_modified_at {
+                    Some(file_modified_at) => {
+                        modified_date.timestamp() != file_modified_at.timestamp()
+                    }
+                    None => modified_date.timestamp() > metadata.file_date.timestamp(),
+                }
+        }
+        Err(err) => {
+            warn!(
+                "Failed to check file {} for modifications: {}",
+                file.rel_path, err
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempdir::TempDir;
+
+    #[test]
+    fn source_unavailable_if_missing_or_empty() {
+        let temp_dir = TempDir::new("source_files_unittest").unwrap();
+        let media_source_path = temp_dir.path().to_str().unwrap();
+        assert_eq!(None, source_unavailable_reason(media_source_path, 0, 0));
+        assert_eq!(None, source_unavailable_reason(media_source_path, 1, 2));
+        assert!(source_unavailable_reason(media_source_path, 0, 2).is_some());
+
+        let missing_path = temp_dir.path().join("missing");
+        assert!(source_unavailable_reason(missing_path.to_str().unwrap(), 1, 2).is_some());
+    }
+
+    #[test]
+    fn digest_file_matches_digest_of_contents() {
+        let temp_dir = TempDir::new("source_files_unittest").unwrap();
+        let file_path = temp_dir.path().join("file.jpg");
+        let file_contents = vec![42u8; 100_000];
+        std::fs::write(&file_path, &file_contents).unwrap();
+
+        assert_eq!(
+            sha256::digest_bytes(&file_contents),
+            digest_file(file_path.to_str().unwrap()).unwrap()
+        );
+    }
+}
@@ -1,18 +1,23 @@
+use std::collections::HashSet;
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use futures::future;
-use glob::{glob_with, MatchOptions};
+use glob::{glob_with, MatchOptions, Pattern};
 use log::warn;
 use persistance::models::File;
 use persistance::FotoboekDatabase;
 use shared::models::FotoboekConfig;
-use shared::path_utils::{abs_pathbuf_to_rel, get_filename};
+use shared::path_utils::{abs_pathbuf_to_rel, get_filename, has_extension};
 
+#[derive(Debug)]
 pub struct SearchAndUpdateResult {
     pub total_count: usize,
     pub added_count: usize,
     pub removed_count: usize,
+    pub modified_count: usize,
+    pub moved_count: usize,
+    pub errors: Vec<String>,
 }
 
 pub async fn search_and_update_db(
@@ -20,20 +25,53 @@ pub async fn search_and_update_db(
     config: &FotoboekConfig,
 ) -> SearchAndUpdateResult {
     let source_paths = search_fs(config);
-    let add_futures = source_paths
+    let registered_rel_paths: HashSet<String> = File::by_file_type(db, "IMAGE")
+        .await
+        .into_iter()
+        .map(|file| file.rel_path)
+        .collect();
+    let new_paths = source_paths
+        .iter()
+        .filter(|source_path| {
+            !registered_rel_paths.contains(abs_pathbuf_to_rel(config, source_path))
+        })
+        .collect::<Vec<_>>();
+    let (moved_count, new_paths) =
+        crate::source_files::update_moved_files(db, config, "IMAGE", &source_paths, new_paths)
+            .await;
+    let add_futures = new_paths
         .iter()
         .map(|source_path| try_add_image(&db, config, source_path))
         .collect::<Vec<_>>();
     let add_results = future::join_all(add_futures).await;
-    let (ok_results, err_results): (Vec<_>, Vec<_>) = add_results.iter().partition(|r| r.is_ok());
-    err_results
+    let mut errors = new_paths
         .iter()
-        .for_each(|r| warn!("Failed to add file: {:?}", r));
+        .zip(add_results.iter())
+        .filter_map(|(source_path, result)| {
+            result
+                .as_ref()
+                .err()
+                .map(|err| format!("Failed to add file {:?}: {}", source_path, err))
+        })
+        .collect::<Vec<_>>();
+    errors.iter().for_each(|err| warn!("{}", err));
+    let added_count = new_paths.len() - errors.len();
+
+    let (removed_count, remove_errors) =
+        crate::source_files::remove_vanished_files(db, config, "IMAGE", &source_paths).await;
+    errors.extend(remove_errors);
+
+    let (modified_count, modify_errors) =
+        crate::source_files::update_modified_files(db, config, "IMAGE", &source_paths).await;
+    errors.extend(modify_errors);
 
     SearchAndUpdateResult {
         total_count: source_paths.len(),
-        added_count: ok_results.len(),
-        removed_count: 0,
+        added_count,
+        removed_count,
+        modified_count,
+        moved_count,
+        errors,
     }
 }
 
@@ -58,16 +96,44 @@ pub async fn try_add_image(
     }
 }
 
+/// Returns true if the given path has one of the configured image or RAW extensions.
+pub fn is_image_path(config: &FotoboekConfig, path: &Path) -> bool {
+    has_extension(path, &config.image_extensions) || is_raw_path(config, path)
+}
+
+/// Returns true if the given path has one of the configured RAW extensions.
+pub fn is_raw_path(config: &FotoboekConfig, path: &Path) -> bool {
+    has_extension(path, &config.raw_extensions)
+}
+
 fn search_fs(cfg: &FotoboekConfig) -> Vec<PathBuf> {
+    search_dir(cfg, &cfg.media_source_path)
+}
+
+/// Recursively searches the given directory for files with one of the configured image or RAW
+/// extensions. Characters of the directory name are matched literally, not as glob patterns.
+pub fn search_dir(config: &FotoboekConfig, dir: &str) -> Vec<PathBuf> {
     let options = MatchOptions {
         case_sensitive: false,
         require_literal_separator: false,
         require_literal_leading_dot: false,
     };
-    glob_with(&format!("{}/**/*.jpg", cfg.media_source_path), options)
-        .unwrap()
-        .chain(glob_with(&format!("{}/**/*.jpeg", cfg.media_source_path), options).unwrap())
-        .filter_map(|entry| entry.ok().map(|path| path.canonicalize().unwrap()))
+    config
+        .image_extensions
+        .iter()
+        .chain(config.raw_extensions.iter())
+        .flat_map(|extension| {
+            let pattern = format!("{}/**/*.{}", Pattern::escape(dir), extension);
+            glob_with(&pattern, options).unwrap()
+        })
+        // Files may vanish or become unreadable while searching
+        .filter_map(|entry| {
+            entry
+                .map_err(|err| err.to_string())
+                .and_then(|path| path.canonicalize().map_err(|err| err.to_string()))
+                .map_err(|err| warn!("Skipping file while searching {}: {}", dir, err))
+                .ok()
+        })
         .collect()
 }
 
@@ -80,6 +146,15 @@ mod tests {
     static NO_IMAGES_DIRNAME: &str = "no_images";
     static TEST_IMAGES_DIRNAME: &str = "test_images";
 
+    fn image_extensions() -> Vec<String> {
+        [
+            "jpg", "jpeg", "png", "webp", "gif", "tif", "tiff", "heic", "heif",
+        ]
+        .iter()
+        .map(|extension| extension.to_string())
+        .collect()
+    }
+
     pub fn setup_temp_dir() -> TempDir {
         let temp_dir = TempDir::new("source_images_unittest").unwrap();
 
@@ -91,6 +166,14 @@ mod tests {
         std::fs::File::create(images_dir_path.clone().join("image2.JPG")).unwrap();
         std::fs::File::create(images_dir_path.clone().join("image3.jpeg")).unwrap();
         std::fs::File::create(images_dir_path.clone().join("image4.JPEG")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image5.png")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image6.webp")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image7.gif")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image8.tif")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image9.HEIC")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image10.heif")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image11.CR2")).unwrap();
+        std::fs::File::create(images_dir_path.clone().join("image12.nef")).unwrap();
         std::fs::File::create(images_dir_path.clone().join("other-file.txt")).unwrap();
 
         temp_dir
@@ -104,8 +187,20 @@ mod tests {
             media_source_path: root,
             file_storage_path: "".to_string(),
             webapp_files_path: "".to_string(),
-            num_worker_threads: 1,
+            worker_pools: vec![],
+            worker_idle_interval_sec: 60,
             task_lock_timeout_sec: 1,
+            task_max_attempts: 5,
+            task_retry_backoff_sec: 60,
+            fs_watcher_enabled: false,
+            scan_on_startup: false,
+            scan_interval_hours: 0,
+            similarity_max_distance: 0,
+            image_extensions: image_extensions(),
+            raw_extensions: vec!["cr2".to_string(), "nef".to_string()],
+            video_extensions: vec![],
+            preview_sizes: vec![],
+            preview_on_demand_max_concurrent: 0,
         };
 
         let source_images = search_fs(&config);
@@ -120,12 +215,24 @@ mod tests {
             media_source_path: root,
             file_storage_path: "".to_string(),
             webapp_files_path: "".to_string(),
-            num_worker_threads: 1,
+            worker_pools: vec![],
+            worker_idle_interval_sec: 60,
             task_lock_timeout_sec: 1,
+            task_max_attempts: 5,
+            task_retry_backoff_sec: 60,
+            fs_watcher_enabled: false,
+            scan_on_startup: false,
+            scan_interval_hours: 0,
+            similarity_max_distance: 0,
+            image_extensions: image_extensions(),
+            raw_extensions: vec!["cr2".to_string(), "nef".to_string()],
+            video_extensions: vec![],
+            preview_sizes: vec![],
+            preview_on_demand_max_concurrent: 0,
         };
 
         let source_images = search_fs(&config);
-        assert_eq!(source_images.len(), 4);
+        assert_eq!(source_images.len(), 12);
     }
 
     #[test]
@@ -136,62 +243,60 @@ mod tests {
             media_source_path: root,
             file_storage_path: "".to_string(),
             webapp_files_path: "".to_string(),
-            num_worker_threads: 1,
+            worker_pools: vec![],
+            worker_idle_interval_sec: 60,
             task_lock_timeout_sec: 1,
+            task_max_attempts: 5,
+            task_retry_backoff_sec: 60,
+            fs_watcher_enabled: false,
+            scan_on_startup: false,
+            scan_interval_hours: 0,
+            similarity_max_distance: 0,
+            image_extensions: image_extensions(),
+            raw_extensions: vec!["cr2".to_string(), "nef".to_string()],
+            video_extensions: vec![],
+            preview_sizes: vec![],
+            preview_on_demand_max_concurrent: 0,
         };
 
         let source_images = search_fs(&config);
-        assert_eq!(source_images.len(), 4);
+        assert_eq!(source_images.len(), 12);
     }
-}
-
-/*
-
-    static NO_IMAGES_DIRNAME: &str = "no_images";
-    static TEST_IMAGES_DIRNAME: &str = "test_images";
 
-    pub fn setup(source_sub_path: &str) -> FotoboekConfig {
-        let temp_dir = TempDir::new("source_images_unittest").unwrap();
-
-        std::fs::create_dir(temp_dir.path().join(NO_IMAGES_DIRNAME)).unwrap();
-
-        let images_dir_path = temp_dir.path().join(TEST_IMAGES_DIRNAME);
-        std::fs::create_dir(&images_dir_path).unwrap();
-        std::fs::File::create(images_dir_path.clone().join("image1.jpg")).unwrap();
-        std::fs::File::create(images_dir_path.clone().join("image2.JPG")).unwrap();
-        std::fs::File::create(images_dir_path.clone().join("image3.jpeg")).unwrap();
-        std::fs::File::create(images_dir_path.clone().join("image4.JPEG")).unwrap();
-        std::fs::File::create(images_dir_path.clone().join("other-file.txt")).unwrap();
-
-        let media_source_path = String::from(temp_dir.path().join(source_sub_path).to_str().unwrap());
-        FotoboekConfig {
-            media_source_path,
+    #[test]
+    fn images_found_in_dir_with_glob_characters() {
+        let temp_dir = setup_temp_dir();
+        let dir_path = temp_dir.path().join("[2021] Holiday *?");
+        std::fs::create_dir(&dir_path).unwrap();
+        std::fs::File::create(dir_path.join("image1.jpg")).unwrap();
+        // Canonicalizing dangling symlinks fails, they are skipped
+        std::os::unix::fs::symlink(dir_path.join("missing.jpg"), dir_path.join("image2.jpg"))
+            .unwrap();
+        let config = FotoboekConfig {
+            media_source_path: String::from(temp_dir.path().to_str().unwrap()),
             file_storage_path: "".to_string(),
-            num_worker_threads: 1,
+            webapp_files_path: "".to_string(),
+            worker_pools: vec![],
+            worker_idle_interval_sec: 60,
             task_lock_timeout_sec: 1,
-        }
-    }
-
-    #[test]
-    fn no_images_found_in_empty_dir() {
-        let config = setup(NO_IMAGES_DIRNAME);
-        let source_images = search_fs(&config);
-        assert_eq!(source_images.len(), 0);
-    }
-
-    #[test]
-    fn test_images_found_in_dir() {
-        let config = setup(TEST_IMAGES_DIRNAME);
-        let source_images = search_fs(&config);
-        assert_eq!(source_images.len(), 4);
-    }
+            task_max_attempts: 5,
+            task_retry_backoff_sec: 60,
+            fs_watcher_enabled: false,
+            scan_on_startup: false,
+            scan_interval_hours: 0,
+            similarity_max_distance: 0,
+            image_extensions: image_extensions(),
+            raw_extensions: vec!["cr2".to_string(), "nef".to_string()],
+            video_extensions: vec![],
+            preview_sizes: vec![],
+            preview_on_demand_max_concurrent: 0,
+        };
 
-    #[test]
-    fn images_are_searched_recursively() {
-        let config = setup("");
-        let source_images = search_fs(&config);
-        assert_eq!(source_images.len(), 4);
+        let source_images = search_dir(&config, dir_path.to_str().unwrap());
+        assert_eq!(
+            source_images,
+            vec![dir_path.join("image1.jpg").canonicalize().unwrap()]
+        );
     }
 }
 
-*/
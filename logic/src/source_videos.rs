@@ -1,18 +1,23 @@
+use std::collections::HashSet;
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use futures::future;
-use glob::{glob_with, MatchOptions};
+use glob::{glob_with, MatchOptions, Pattern};
 use log::warn;
 use persistance::models::File;
 use persistance::FotoboekDatabase;
 use shared::models::FotoboekConfig;
-use shared::path_utils::{abs_pathbuf_to_rel, get_filename};
+use shared::path_utils::{abs_pathbuf_to_rel, get_filename, has_extension};
 
+#[derive(Debug)]
 pub struct SearchAndUpdateResult {
     pub total_count: usize,
     pub added_count: usize,
     pub removed_count: usize,
+    pub modified_count: usize,
+    pub moved_count: usize,
+    pub errors: Vec<String>,
 }
 
 pub async fn search_and_update_db(
@@ -20,20 +25,53 @@ pub async fn search_and_update_db(
     config: &FotoboekConfig,
 ) -> SearchAndUpdateResult {
     let source_paths = search_fs(config);
-    let add_futures = source_paths
+    let registered_rel_paths: HashSet<String> = File::by_file_type(db, "VIDEO")
+        .await
+        .into_iter()
+        .map(|file| file.rel_path)
+        .collect();
+    let new_paths = source_paths
+        .iter()
+        .filter(|source_path| {
+            !registered_rel_paths.contains(abs_pathbuf_to_rel(config, source_path))
+        })
+        .collect::<Vec<_>>();
+    let (moved_count, new_paths) =
+        crate::source_files::update_moved_files(db, config, "VIDEO", &source_paths, new_paths)
+            .await;
+    let add_futures = new_paths
         .iter()
         .map(|source_path| try_add_video(&db, config, source_path))
         .collect::<Vec<_>>();
     let add_results = future::join_all(add_futures).await;
-    let (ok_results, err_results): (Vec<_>, Vec<_>) = add_results.iter().partition(|r| r.is_ok());
-    err_results
+    let mut errors = new_paths
         .iter()
-        .for_each(|r| warn!("Failed to add file: {:?}", r));
+        .zip(add_results.iter())
+        .filter_map(|(source_path, result)| {
+            result
+                .as_ref()
+                .err()
+                .map(|err| format!("Failed to add file {:?}: {}", source_path, err))
+        })
+        .collect::<Vec<_>>();
+    errors.iter().for_each(|err| warn!("{}", err));
+    let added_count = new_paths.len() - errors.len();
+
+    let (removed_count, remove_errors) =
+        crate::source_files::remove_vanished_files(db, config, "VIDEO", &source_paths).await;
+    errors.extend(remove_errors);
+
+    let (modified_count, modify_errors) =
+        crate::source_files::update_modified_files(db, config, "VIDEO", &source_paths).await;
+    errors.extend(modify_errors);
 
     SearchAndUpdateResult {
         total_count: source_paths.len(),
-        added_count: ok_results.len(),
-        removed_count: 0,
+        added_count,
+        removed_count,
+        modified_count,
+        moved_count,
+        errors,
     }
 }
 
@@ -58,14 +96,37 @@ pub async fn try_add_video(
     }
 }
 
+/// Returns true if the given path has one of the configured video extensions.
+pub fn is_video_path(config: &FotoboekConfig, path: &Path) -> bool {
+    has_extension(path, &config.video_extensions)
+}
+
 fn search_fs(cfg: &FotoboekConfig) -> Vec<PathBuf> {
+    search_dir(cfg, &cfg.media_source_path)
+}
+
+/// Recursively searches the given directory for files with one of the configured video extensions.
+/// Characters of the directory name are matched literally, not as glob patterns.
+pub fn search_dir(config: &FotoboekConfig, dir: &str) -> Vec<PathBuf> {
     let options = MatchOptions {
         case_sensitive: false,
         require_literal_separator: false,
         require_literal_leading_dot: false,
     };
-    glob_with(&format!("{}/**/*.mp4", cfg.media_source_path), options)
-        .unwrap()
-        .filter_map(|entry| entry.ok().map(|path| path.canonicalize().unwrap()))
+    config
+        .video_extensions
+        .iter()
+        .flat_map(|extension| {
+            let pattern = format!("{}/**/*.{}", Pattern::escape(dir), extension);
+            glob_with(&pattern, options).unwrap()
+        })
+        // Files may vanish or become unreadable while searching
+        .filter_map(|entry| {
+            entry
+                .map_err(|err| err.to_string())
+                .and_then(|path| path.canonicalize().map_err(|err| err.to_string()))
+                .map_err(|err| warn!("Skipping file while searching {}: {}", dir, err))
+                .ok()
+        })
         .collect()
 }
@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, error, info, warn};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use persistance::models::File;
+use persistance::FotoboekDatabase;
+use shared::models::FotoboekConfig;
+use shared::path_utils::abs_pathbuf_to_rel;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio::task;
+
+use crate::{scan, source_files, source_images, source_videos};
+
+/// Events of the same path that happen within this delay are combined into one event.
+const DEBOUNCE_DELAY_SEC: u64 = 5;
+
+/// Watches the media source path for filesystem events and keeps the database up to date.
+pub fn spawn(db: FotoboekDatabase, config: &FotoboekConfig) {
+    let config_copy = config.clone();
+    let (event_tx, mut event_rx) = unbounded_channel();
+
+    let media_source_path = config.media_source_path.clone();
+    thread::spawn(move || watch(media_source_path, event_tx));
+
+    task::spawn(async move {
+        while let Some(event) = event_rx.recv().await {
+            handle_event(&db, &config_copy, event).await;
+        }
+    });
+}
+
+/// Blocks the current thread and forwards all debounced events to the given sender.
+fn watch(path: String, event_tx: UnboundedSender<DebouncedEvent>) {
+    let (tx, rx) = channel();
+    let mut watcher = match watcher(tx, Duration::from_secs(DEBOUNCE_DELAY_SEC)) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            error!("Failed to create filesystem watcher: {:?}", err);
+            return;
+        }
+    };
+    if let Err(err) = watcher.watch(&path, RecursiveMode::Recursive) {
+        error!("Failed to watch {}: {:?}", path, err);
+        return;
+    }
+    info!("Watching {} for filesystem events", path);
+
+    for event in rx {
+        if event_tx.send(event).is_err() {
+            break;
+        }
+    }
+}
+
+async fn handle_event(db: &FotoboekDatabase, config: &FotoboekConfig, event: DebouncedEvent) {
+    debug!("Handling filesystem event {:?}", event);
+
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+            add_path(db, config, &path).await
+        }
+        DebouncedEvent::Remove(path) => remove_path(db, config, &path).await,
+        DebouncedEvent::Rename(from_path, to_path) => {
+            move_path(db, config, &from_path, &to_path).await
+        }
+        DebouncedEvent::Rescan => {
+            if let Err(err) = scan::scan(db, config, scan::TRIGGER_WATCHER).await {
+                error!("Failed to rescan after watcher event: {}", err);
+            }
+        }
+        DebouncedEvent::Error(err, path) => {
+            warn!("Filesystem watcher error: {:?}, path: {:?}", err, path)
+        }
+        _ => (),
+    }
+}
+
+async fn add_path(db: &FotoboekDatabase, config: &FotoboekConfig, path: &PathBuf) {
+    let abs_path = match path.canonicalize() {
+        Ok(abs_path) => abs_path,
+        Err(err) => {
+            debug!("Ignoring vanished path {:?}: {}", path, err);
+            return;
+        }
+    };
+
+    let (image_paths, video_paths) = if abs_path.is_dir() {
+        let dir = abs_path.to_str().unwrap();
+        (
+            source_images::search_dir(config, dir),
+            source_videos::search_dir(config, dir),
+        )
+    } else if source_images::is_image_path(config, &abs_path) {
+        (vec![abs_path], vec![])
+    } else if source_videos::is_video_path(config, &abs_path) {
+        (vec![], vec![abs_path])
+    } else {
+        return;
+    };
+
+    for image_path in image_paths.iter() {
+        if update_if_registered(db, config, image_path).await {
+            continue;
+        }
+        if let Err(err) = source_images::try_add_image(db, config, image_path).await {
+            debug!("Image {:?} not added: {}", image_path, err);
+        }
+    }
+    for video_path in video_paths.iter() {
+        if update_if_registered(db, config, video_path).await {
+            continue;
+        }
+        if let Err(err) = source_videos::try_add_video(db, config, video_path).await {
+            debug!("Video {:?} not added: {}", video_path, err);
+        }
+    }
+}
+
+/// Updates the file registered for the given path in case it was modified. Returns false if no
+/// file is registered for the path.
+async fn update_if_registered(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    abs_path: &PathBuf,
+) -> bool {
+    let rel_path = abs_pathbuf_to_rel(config, abs_path);
+    match File::by_rel_path(db, rel_path).await {
+        Some(file) => {
+            if let Err(err) = source_files::update_if_modified(db, config, &file).await {
+                warn!("Failed to update {}: {}", rel_path, err);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Moves the files registered for the source path in place, so they keep their metadata and
+/// previews. Falls back to removing and adding the paths if that is not possible.
+async fn move_path(
+    db: &FotoboekDatabase,
+    config: &FotoboekConfig,
+    from_path: &Path,
+    to_path: &PathBuf,
+) {
+    let from_rel_path = from_path
+        .strip_prefix(&config.media_source_path)
+        .ok()
+        .and_then(|rel_path| rel_path.to_str());
+    let to_abs_path = to_path
+        .canonicalize()
+        .ok()
+        .filter(|abs_path| {
+            abs_path.is_dir()
+                || source_images::is_image_path(config, abs_path)
+                || source_videos::is_video_path(config, abs_path)
+        })
+        .filter(|abs_path| abs_path.starts_with(&config.media_source_path));
+
+    if let (Some(from_rel_path), Some(to_abs_path)) = (from_rel_path, to_abs_path) {
+        let to_rel_path = abs_pathbuf_to_rel(config, &to_abs_path);
+        match source_files::try_move_path(db, from_rel_path, to_rel_path).await {
+            Ok(0) => (),
+            Ok(moved_count) => {
+                debug!("Moved {} file(s) to {}", moved_count, to_rel_path);
+                return;
+            }
+            Err(err) => warn!(
+                "Failed to move {} to {}: {}",
+                from_rel_path, to_rel_path, err
+            ),
+        }
+    }
+
+    remove_path(db, config, from_path).await;
+    add_path(db, config, to_path).await;
+}
+
+async fn remove_path(db: &FotoboekDatabase, config: &FotoboekConfig, path: &Path) {
+    let rel_path = match path.strip_prefix(&config.media_source_path) {
+        Ok(rel_path) => rel_path.to_str().unwrap(),
+        Err(_) => {
+            warn!("Ignoring path outside of the media source path: {:?}", path);
+            return;
+        }
+    };
+
+    match source_files::try_remove_path(db, config, rel_path).await {
+        Ok(removed_count) => debug!("Removed {} file(s) for {}", removed_count, rel_path),
+        Err(err) => warn!("Failed to remove {}: {}", rel_path, err),
+    }
+}
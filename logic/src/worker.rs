@@ -1,44 +1,175 @@
-use log::{debug, error, trace};
+use futures::FutureExt;
+use lazy_static::lazy_static;
+use log::{debug, error, trace, warn};
 use persistance::models::Task;
 use persistance::FotoboekDatabase;
-use shared::models::FotoboekConfig;
+use shared::models::{FotoboekConfig, WorkerPool};
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::Mutex;
+use tokio::sync::watch;
 use tokio::task;
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, timeout, Duration};
 
-pub fn spawn(db: FotoboekDatabase, config: &FotoboekConfig, worker_id: usize) {
+lazy_static! {
+    /// Changes whenever tasks are enqueued, idle workers wait for it instead of polling.
+    static ref TASKS_ENQUEUED: watch::Sender<()> = watch::channel(()).0;
+    /// Distinguishes the lock owners of this process from those of earlier runs.
+    static ref INSTANCE_ID: i64 = chrono::Utc::now().timestamp_millis();
+    /// Lock owner by id of the tasks the workers are working on, renewed by the heartbeat.
+    static ref HELD_LOCKS: Mutex<HashMap<i32, String>> = Mutex::new(HashMap::new());
+}
+
+/// Wakes up all idle workers, must be called after tasks were created or became workable.
+pub fn notify_tasks_enqueued() {
+    TASKS_ENQUEUED.send_replace(());
+}
+
+/// Spawns a worker of the pool, which only runs tasks of the modules of the pool.
+pub fn spawn(db: FotoboekDatabase, config: &FotoboekConfig, pool: &WorkerPool, index: usize) {
     let config_copy = config.clone();
+    let modules = pool.modules.clone();
+    let worker_name = format!("{}-{}", pool.name, index);
+    let lock_owner = format!("{}-{}", *INSTANCE_ID, worker_name);
+    let idle_interval = Duration::from_secs(config.worker_idle_interval_sec as u64);
+    let mut tasks_enqueued = TASKS_ENQUEUED.subscribe();
     task::spawn(async move {
         loop {
+            // Tasks enqueued from now on wake up the worker, even before it starts waiting
+            tasks_enqueued.borrow_and_update();
             let task_option =
-                Task::next_workable_by_priority_and_lock(&db, &config_copy, worker_id).await;
+                Task::next_workable_by_priority_and_lock(&db, &config_copy, &modules, &lock_owner)
+                    .await;
 
             if let Some(task) = task_option {
                 debug!(
                     "Worker {} locked task {:?} and starts working",
-                    worker_id, task
+                    worker_name, task
                 );
-                run_task(&db, &config_copy, task).await;
+                run_task(&db, &config_copy, task, &lock_owner).await;
             } else {
-                trace!("Worker {} has no workable tasks, going to sleep", worker_id);
-                sleep(Duration::from_secs(60)).await;
+                trace!(
+                    "Worker {} has no workable tasks, going to sleep",
+                    worker_name
+                );
+                // Tasks also become workable by time, e.g. when their lock or retry backoff expires
+                if timeout(idle_interval, tasks_enqueued.changed())
+                    .await
+                    .is_ok()
+                {
+                    trace!("Worker {} woke up on enqueued tasks", worker_name);
+                }
             }
         }
     });
 }
 
-async fn run_task(db: &FotoboekDatabase, config: &FotoboekConfig, task: Task) {
-    match crate::modules::run_task(db, config, &task).await {
-        Ok(_) => {
-            let task_id = task.id;
-            let delete_result = task.delete(db).await;
-            if let Err(err) = delete_result {
-                error!(
-                    "Deleting finished task failed, task id: {:?}, error: {}",
-                    task_id, err
-                );
+/// Renews the locks of all tasks the workers are working on, so long running tasks like transcodes
+/// are not picked up by another worker once the lock timeout passed. Uses its own database
+/// connection, as the workers may block theirs for a long time.
+pub fn spawn_heartbeat(db: FotoboekDatabase, config: &FotoboekConfig) {
+    let interval = Duration::from_secs((config.task_lock_timeout_sec as u64 / 3).max(1));
+    task::spawn(async move {
+        loop {
+            sleep(interval).await;
+            let held_locks: Vec<(i32, String)> = HELD_LOCKS
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(task_id, lock_owner)| (*task_id, lock_owner.clone()))
+                .collect();
+            for (task_id, lock_owner) in held_locks {
+                match Task::renew_lock(&db, task_id, &lock_owner).await {
+                    Ok(true) => trace!("Renewed lock of task {} for {}", task_id, lock_owner),
+                    Ok(false) => {
+                        warn!(
+                            "Lock of task {} was lost by {}, another worker may run it as well",
+                            task_id, lock_owner
+                        );
+                        HELD_LOCKS.lock().unwrap().remove(&task_id);
+                    }
+                    Err(err) => error!("Renewing lock of task {} failed: {}", task_id, err),
+                }
             }
-            ()
         }
-        Err(err) => error!("Running task {:?} failed with error: {}", task, err),
+    });
+}
+
+/// Registers the lock of a task for renewal by the heartbeat as long as it is alive.
+struct HeldLock {
+    task_id: i32,
+}
+
+impl HeldLock {
+    fn new(task_id: i32, lock_owner: &str) -> HeldLock {
+        HELD_LOCKS
+            .lock()
+            .unwrap()
+            .insert(task_id, lock_owner.to_string());
+        HeldLock { task_id }
+    }
+}
+
+impl Drop for HeldLock {
+    fn drop(&mut self) {
+        HELD_LOCKS.lock().unwrap().remove(&self.task_id);
+    }
+}
+
+async fn run_task(db: &FotoboekDatabase, config: &FotoboekConfig, task: Task, lock_owner: &str) {
+    let task_id = task.id;
+    let held_lock = task_id.map(|task_id| HeldLock::new(task_id, lock_owner));
+    // A panicking module must neither stop the worker nor keep the task locked forever
+    let result = AssertUnwindSafe(crate::modules::run_task(db, config, &task))
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|panic| Err(format!("Module panicked: {}", panic_message(&*panic))));
+    drop(held_lock);
+
+    match result {
+        Ok(_) => match task.delete_if_locked_by(db, lock_owner).await {
+            // Tasks depending on the finished task are workable now
+            Ok(true) => notify_tasks_enqueued(),
+            Ok(false) => warn!(
+                "Task {:?} finished, but its lock was lost to another worker",
+                task_id
+            ),
+            Err(err) => error!(
+                "Deleting finished task failed, task id: {:?}, error: {}",
+                task_id, err
+            ),
+        },
+        Err(err) => {
+            warn!("Running task {:?} failed with error: {}", task, err);
+            match task.record_failure(db, config, lock_owner, &err).await {
+                Ok(Some(task)) if task.is_failed() => error!(
+                    "Task {:?} failed {} times, giving up, last error: {}",
+                    task_id, task.attempts, err
+                ),
+                Ok(Some(task)) => debug!(
+                    "Task {:?} will be retried at {}",
+                    task_id, task.next_attempt_at
+                ),
+                Ok(None) => warn!(
+                    "Task {:?} failed, but its lock was lost to another worker",
+                    task_id
+                ),
+                Err(record_err) => error!(
+                    "Recording failure of task failed, task id: {:?}, error: {}",
+                    task_id, record_err
+                ),
+            }
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
     }
 }
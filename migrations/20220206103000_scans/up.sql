@@ -0,0 +1,14 @@
+CREATE TABLE scans (
+    id INTEGER PRIMARY KEY,
+    triggered_by TEXT NOT NULL,
+    status TEXT NOT NULL,
+    started_at TIMESTAMP NOT NULL,
+    finished_at TIMESTAMP NULL,
+    images_total INTEGER NOT NULL DEFAULT 0,
+    images_added INTEGER NOT NULL DEFAULT 0,
+    images_removed INTEGER NOT NULL DEFAULT 0,
+    videos_total INTEGER NOT NULL DEFAULT 0,
+    videos_added INTEGER NOT NULL DEFAULT 0,
+    videos_removed INTEGER NOT NULL DEFAULT 0,
+    errors TEXT NULL
+);
@@ -0,0 +1,4 @@
+ALTER TABLE scans
+    DROP COLUMN images_modified;
+ALTER TABLE scans
+    DROP COLUMN videos_modified;
@@ -0,0 +1,4 @@
+ALTER TABLE scans
+    ADD COLUMN images_modified INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE scans
+    ADD COLUMN videos_modified INTEGER NOT NULL DEFAULT 0;
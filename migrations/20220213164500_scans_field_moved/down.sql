@@ -0,0 +1,4 @@
+ALTER TABLE scans
+    DROP COLUMN images_moved;
+ALTER TABLE scans
+    DROP COLUMN videos_moved;
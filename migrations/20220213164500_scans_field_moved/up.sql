@@ -0,0 +1,4 @@
+ALTER TABLE scans
+    ADD COLUMN images_moved INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE scans
+    ADD COLUMN videos_moved INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+DROP INDEX file_metadata__file_hash;
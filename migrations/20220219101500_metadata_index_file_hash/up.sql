@@ -0,0 +1,2 @@
+CREATE INDEX file_metadata__file_hash
+ON file_metadata(file_hash);
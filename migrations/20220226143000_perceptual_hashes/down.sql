@@ -0,0 +1,2 @@
+DELETE FROM tasks WHERE module = 'phash';
+DROP TABLE perceptual_hashes;
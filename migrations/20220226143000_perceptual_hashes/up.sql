@@ -0,0 +1,10 @@
+CREATE TABLE perceptual_hashes (
+    file_id INTEGER PRIMARY KEY,
+    dhash BIGINT NOT NULL,
+    FOREIGN KEY (file_id) REFERENCES files (id) ON DELETE CASCADE
+);
+
+-- Calculate perceptual hashes for all files that were indexed before
+INSERT INTO tasks (file_id, module, priority, max_worker_id)
+SELECT id, 'phash', 400, 1024
+FROM files;
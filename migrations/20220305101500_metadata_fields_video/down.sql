@@ -0,0 +1,10 @@
+ALTER TABLE file_metadata
+    DROP COLUMN video_rotation;
+ALTER TABLE file_metadata
+    DROP COLUMN video_frame_rate;
+ALTER TABLE file_metadata
+    DROP COLUMN audio_codec;
+ALTER TABLE file_metadata
+    DROP COLUMN video_codec;
+ALTER TABLE file_metadata
+    DROP COLUMN video_duration;
@@ -0,0 +1,17 @@
+ALTER TABLE file_metadata
+    ADD COLUMN video_duration INTEGER NULL;
+ALTER TABLE file_metadata
+    ADD COLUMN video_codec TEXT NULL;
+ALTER TABLE file_metadata
+    ADD COLUMN audio_codec TEXT NULL;
+ALTER TABLE file_metadata
+    ADD COLUMN video_frame_rate FLOAT NULL;
+ALTER TABLE file_metadata
+    ADD COLUMN video_rotation INTEGER NULL;
+
+-- Extract the metadata of all videos again to populate the new fields
+INSERT INTO tasks (file_id, module, priority, max_worker_id)
+SELECT id, 'metadata', 100, 1024
+FROM files
+WHERE file_type = 'VIDEO'
+    AND NOT EXISTS (SELECT 1 FROM tasks WHERE file_id = files.id AND module = 'metadata');
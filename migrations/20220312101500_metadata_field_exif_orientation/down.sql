@@ -0,0 +1,2 @@
+ALTER TABLE file_metadata
+    DROP COLUMN exif_orientation;
@@ -0,0 +1,10 @@
+ALTER TABLE file_metadata
+    ADD COLUMN exif_orientation INTEGER NULL;
+
+-- Extract the metadata of all images again to populate the new field and the resolution after
+-- rotation. Previews of rotated images are generated anew by the metadata task.
+INSERT INTO tasks (file_id, module, priority, max_worker_id)
+SELECT id, 'metadata', 100, 1024
+FROM files
+WHERE file_type = 'IMAGE'
+    AND NOT EXISTS (SELECT 1 FROM tasks WHERE file_id = files.id AND module = 'metadata');
@@ -0,0 +1,2 @@
+-- Sprite sheets are deleted together with the previews, there is nothing to revert
+SELECT 1;
@@ -0,0 +1,6 @@
+-- Generate the sprite sheets of all existing videos, existing previews are kept
+INSERT INTO tasks (file_id, module, priority, max_worker_id)
+SELECT id, 'preview', 200, 1024
+FROM files
+WHERE file_type = 'VIDEO'
+    AND NOT EXISTS (SELECT 1 FROM tasks WHERE file_id = files.id AND module = 'preview');
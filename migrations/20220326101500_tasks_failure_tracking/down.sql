@@ -0,0 +1,8 @@
+ALTER TABLE tasks
+    DROP COLUMN failed_at;
+ALTER TABLE tasks
+    DROP COLUMN next_attempt_at;
+ALTER TABLE tasks
+    DROP COLUMN last_error;
+ALTER TABLE tasks
+    DROP COLUMN attempts;
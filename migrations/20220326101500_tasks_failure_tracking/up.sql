@@ -0,0 +1,8 @@
+ALTER TABLE tasks
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE tasks
+    ADD COLUMN last_error TEXT NULL;
+ALTER TABLE tasks
+    ADD COLUMN next_attempt_at TIMESTAMP NOT NULL DEFAULT "1970-01-01 00:00:00";
+ALTER TABLE tasks
+    ADD COLUMN failed_at TIMESTAMP NULL;
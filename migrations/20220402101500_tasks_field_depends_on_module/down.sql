@@ -0,0 +1,4 @@
+DROP INDEX tasks__file_id_module;
+
+ALTER TABLE tasks
+    DROP COLUMN depends_on_module;
@@ -0,0 +1,15 @@
+ALTER TABLE tasks
+    ADD COLUMN depends_on_module TEXT NULL;
+
+-- Previews and transcodes need the file hash from the metadata, perceptual hashes are calculated
+-- from the small preview
+UPDATE tasks
+SET depends_on_module = 'metadata'
+WHERE module IN ('preview', 'transcode');
+
+UPDATE tasks
+SET depends_on_module = 'preview'
+WHERE module = 'phash';
+
+CREATE INDEX tasks__file_id_module
+ON tasks(file_id, module);
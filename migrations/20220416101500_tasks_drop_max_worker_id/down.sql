@@ -0,0 +1,6 @@
+ALTER TABLE tasks
+    ADD COLUMN max_worker_id INTEGER NOT NULL DEFAULT 1024;
+
+UPDATE tasks
+SET max_worker_id = 0
+WHERE module = 'transcode';
@@ -0,0 +1,30 @@
+-- Worker pools configured by module replace the maximum worker id of the tasks. SQLite supports
+-- DROP COLUMN only since 3.35, so the table is rebuilt without the column.
+CREATE TABLE tasks_new (
+    id INTEGER PRIMARY KEY,
+    file_id INTEGER NOT NULL,
+    module TEXT NOT NULL,
+    priority INTEGER NOT NULL,
+    work_started_at TIMESTAMP NOT NULL DEFAULT "1970-01-01 00:00:00",
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT NULL,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT "1970-01-01 00:00:00",
+    failed_at TIMESTAMP NULL,
+    depends_on_module TEXT NULL,
+    locked_by TEXT NULL,
+    FOREIGN KEY (file_id) REFERENCES files (id) ON DELETE CASCADE
+);
+
+INSERT INTO tasks_new (id, file_id, module, priority, work_started_at, attempts, last_error,
+                       next_attempt_at, failed_at, depends_on_module, locked_by)
+SELECT id, file_id, module, priority, work_started_at, attempts, last_error,
+       next_attempt_at, failed_at, depends_on_module, locked_by
+FROM tasks;
+
+DROP TABLE tasks;
+
+ALTER TABLE tasks_new
+    RENAME TO tasks;
+
+CREATE INDEX tasks__file_id_module
+ON tasks(file_id, module);
@@ -0,0 +1,2 @@
+ALTER TABLE file_metadata
+    ADD COLUMN file_modified_at TIMESTAMP NULL;
@@ -0,0 +1,3 @@
+UPDATE tasks
+SET failed_at = NULL, last_error = NULL
+WHERE last_error = 'Prerequisite failed';
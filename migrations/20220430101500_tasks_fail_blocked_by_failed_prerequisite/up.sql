@@ -0,0 +1,18 @@
+-- Tasks depending on failed tasks are never run, they fail as well
+WITH RECURSIVE blocked(id, file_id, module) AS (
+    SELECT task.id, task.file_id, task.module
+    FROM tasks AS task
+    INNER JOIN tasks AS prerequisite
+        ON prerequisite.file_id = task.file_id
+        AND prerequisite.module = task.depends_on_module
+    WHERE prerequisite.failed_at IS NOT NULL
+    UNION
+    SELECT task.id, task.file_id, task.module
+    FROM tasks AS task
+    INNER JOIN blocked
+        ON task.file_id = blocked.file_id
+        AND task.depends_on_module = blocked.module
+)
+UPDATE tasks
+SET failed_at = CURRENT_TIMESTAMP, last_error = 'Prerequisite failed'
+WHERE id IN (SELECT id FROM blocked) AND failed_at IS NULL;
@@ -1,9 +1,16 @@
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use shared::models::{FotoboekConfig, PreviewSize};
 
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of frames in the sprite sheet of a video, shown when scrubbing over its preview.
+pub const VIDEO_SPRITE_FRAMES: usize = 10;
+
 pub fn init(config: &FotoboekConfig) {
     fs::create_dir_all(preview_base_dir_path(&config)).unwrap();
     fs::create_dir_all(video_base_dir_path(&config)).unwrap();
@@ -14,21 +21,173 @@ pub fn store_preview(
     file_hash: &String,
     preview_size: &PreviewSize,
     preview_bytes: &Vec<u8>,
+) -> Result<(), String> {
+    store_in_preview_dir(
+        config,
+        file_hash,
+        &file_preview_path(config, file_hash, preview_size),
+        preview_bytes,
+    )
+}
+
+pub fn store_video_sprite(
+    config: &FotoboekConfig,
+    file_hash: &String,
+    sprite_bytes: &Vec<u8>,
+) -> Result<(), String> {
+    store_in_preview_dir(
+        config,
+        file_hash,
+        &video_sprite_path(config, file_hash),
+        sprite_bytes,
+    )
+}
+
+fn store_in_preview_dir(
+    config: &FotoboekConfig,
+    file_hash: &String,
+    file_path: &str,
+    bytes: &Vec<u8>,
 ) -> Result<(), String> {
     assert_eq!(file_hash.len(), 64);
 
     // Make sure, the directory exists
     fs::create_dir_all(preview_dir_path(config, file_hash)).unwrap();
 
-    let file_path = file_preview_path(config, file_hash, preview_size);
-    let mut file = File::create(file_path).map_err(|err| err.to_string())?;
-    file.write_all(preview_bytes)
-        .map_err(|err| err.to_string())?;
-    file.flush().map_err(|err| err.to_string())?;
+    // Previews are generated by the workers and on demand at the same time, so the preview is
+    // written to a temporary file first and then renamed, to never serve half written previews
+    let temp_file_path = format!(
+        "{}.{}.tmp",
+        file_path,
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let result = File::create(&temp_file_path)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.flush()
+        })
+        .and_then(|_| fs::rename(&temp_file_path, file_path));
+    if let Err(err) = result {
+        // Never leave half written temporary files behind
+        let _ = fs::remove_file(&temp_file_path);
+        return Err(err.to_string());
+    }
+
+    Ok(())
+}
+
+/// Returns true if the preview image of the given file hash and size exists.
+pub fn preview_exists(
+    config: &FotoboekConfig,
+    file_hash: &String,
+    preview_size: &PreviewSize,
+) -> bool {
+    Path::new(&file_preview_path(config, file_hash, preview_size)).exists()
+}
+
+/// Returns true if all configured preview images of the given file hash exist.
+pub fn previews_exist(config: &FotoboekConfig, file_hash: &String) -> bool {
+    config
+        .preview_sizes
+        .iter()
+        .all(|preview_size| preview_exists(config, file_hash, preview_size))
+}
+
+/// Returns true if the sprite sheet of the video with the given file hash exists.
+pub fn video_sprite_exists(config: &FotoboekConfig, file_hash: &String) -> bool {
+    Path::new(&video_sprite_path(config, file_hash)).exists()
+}
+
+/// Returns true if the transcoded video of the given file hash exists.
+pub fn video_exists(config: &FotoboekConfig, file_hash: &String) -> bool {
+    Path::new(&video_path(config, file_hash)).exists()
+}
 
+/// Deletes all preview images of the given file hash, including those of sizes that are no longer
+/// configured, video sprite sheets and leftover temporary files. Missing previews are ignored.
+pub fn delete_previews(config: &FotoboekConfig, file_hash: &String) -> Result<(), String> {
+    let dir_path = preview_dir_path(config, file_hash);
+    let entries = match fs::read_dir(&dir_path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(format!("{}: {}", err, dir_path)),
+    };
+
+    // Matches previews like `small-<hash>.webp` and temporary files like `small-<hash>.webp.3.tmp`
+    let infix = format!("-{}.", file_hash);
+    for entry in entries {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        let is_preview = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .map(|file_name| file_name.contains(&infix))
+            .unwrap_or(false);
+        if is_preview {
+            remove_file_if_exists(path.to_str().unwrap())?;
+        }
+    }
     Ok(())
 }
 
+/// Deletes all preview images whose size or format is no longer configured, e.g. because the
+/// format of a preview size changed. Returns the number of deleted previews.
+pub fn delete_orphaned_previews(config: &FotoboekConfig) -> Result<usize, String> {
+    let base_dir_path = preview_base_dir_path(config);
+    let dirs = fs::read_dir(&base_dir_path).map_err(|err| format!("{}: {}", err, base_dir_path))?;
+
+    let mut deleted_count = 0;
+    for dir in dirs {
+        let dir_path = dir.map_err(|err| err.to_string())?.path();
+        if !dir_path.is_dir() {
+            continue;
+        }
+        let entries = fs::read_dir(&dir_path).map_err(|err| err.to_string())?;
+        for entry in entries {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            let is_orphaned = path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .map(|file_name| is_orphaned_preview(&config.preview_sizes, file_name))
+                .unwrap_or(false);
+            if is_orphaned {
+                remove_file_if_exists(path.to_str().unwrap())?;
+                deleted_count += 1;
+            }
+        }
+    }
+    Ok(deleted_count)
+}
+
+/// Returns true if the file name is the one of a preview, but matches none of the preview sizes.
+/// Video sprite sheets and temporary files are never orphaned previews.
+fn is_orphaned_preview(preview_sizes: &[PreviewSize], file_name: &str) -> bool {
+    if file_name.starts_with("video-sprite-") {
+        return false;
+    }
+    let (stem, extension) = match file_name.split_once('.') {
+        Some((stem, extension)) if !extension.contains('.') => (stem, extension),
+        _ => return false,
+    };
+    match stem.split_once('-') {
+        Some((name, file_hash)) if file_hash.len() == 64 => !preview_sizes
+            .iter()
+            .any(|size| size.name == name && size.format.extension() == extension),
+        _ => false,
+    }
+}
+
+/// Deletes the transcoded video of the given file hash. A missing video is ignored.
+pub fn delete_video(config: &FotoboekConfig, file_hash: &String) -> Result<(), String> {
+    remove_file_if_exists(&video_path(config, file_hash))
+}
+
+fn remove_file_if_exists(path: &str) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(format!("{}: {}", err, path)),
+        _ => Ok(()),
+    }
+}
+
 /// Returns the path to the base folder that contains all preview images.
 fn preview_base_dir_path(config: &FotoboekConfig) -> String {
     format!("{}/previews", config.file_storage_path)
@@ -57,14 +216,21 @@ pub fn file_preview_path(
     file_hash: &String,
     preview_size: &PreviewSize,
 ) -> String {
-    let size_prefix = match preview_size {
-        PreviewSize::Small => "small",
-        PreviewSize::Large => "large",
-    };
     format!(
-        "{}/{}-{}.webp",
+        "{}/{}-{}.{}",
+        preview_dir_path(config, file_hash),
+        preview_size.name,
+        file_hash,
+        preview_size.format.extension()
+    )
+}
+
+/// Returns the path to the sprite sheet of the video. Preview size names can not contain dashes, so
+/// the name never collides with a preview.
+pub fn video_sprite_path(config: &FotoboekConfig, file_hash: &String) -> String {
+    format!(
+        "{}/video-sprite-{}.webp",
         preview_dir_path(config, file_hash),
-        size_prefix,
         file_hash
     )
 }
@@ -72,3 +238,25 @@ pub fn file_preview_path(
 pub fn video_path(config: &FotoboekConfig, file_hash: &String) -> String {
     format!("{}/{}.webm", video_dir_path(config, file_hash), file_hash)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orphaned_previews() {
+        let preview_sizes: Vec<PreviewSize> = vec![
+            "small:cover:200:webp:85".parse().unwrap(),
+            "large:contain:2000:avif:60".parse().unwrap(),
+        ];
+        let file_hash = "a".repeat(64);
+
+        let is_orphaned = |file_name: String| is_orphaned_preview(&preview_sizes, &file_name);
+        assert!(!is_orphaned(format!("small-{}.webp", file_hash)));
+        assert!(!is_orphaned(format!("large-{}.avif", file_hash)));
+        assert!(!is_orphaned(format!("video-sprite-{}.webp", file_hash)));
+        assert!(!is_orphaned(format!("small-{}.webp.3.tmp", file_hash)));
+        assert!(is_orphaned(format!("large-{}.webp", file_hash)));
+        assert!(is_orphaned(format!("medium-{}.webp", file_hash)));
+    }
+}
@@ -3,6 +3,7 @@ use serde::Serialize;
 
 use crate::schema::files;
 use crate::schema::files::dsl;
+use crate::schema::{file_metadata, perceptual_hashes, tasks};
 use crate::FotoboekDatabase;
 
 #[derive(Insertable, Queryable, Serialize)]
@@ -29,6 +30,57 @@ impl File {
         .await
     }
 
+    pub async fn by_ids(db: &FotoboekDatabase, file_ids: Vec<i32>) -> Vec<File> {
+        db.run(move |conn| {
+            dsl::files
+                .filter(dsl::id.eq_any(file_ids))
+                .load(conn)
+                .expect("Load files by ids failed")
+        })
+        .await
+    }
+
+    pub async fn by_file_type(db: &FotoboekDatabase, file_type: &str) -> Vec<File> {
+        let file_type = file_type.to_string();
+        db.run(move |conn| {
+            dsl::files
+                .filter(dsl::file_type.eq(file_type))
+                .load(conn)
+                .expect("Load files by file_type failed")
+        })
+        .await
+    }
+
+    pub async fn by_rel_path(db: &FotoboekDatabase, rel_path: &str) -> Option<File> {
+        let rel_path = rel_path.to_string();
+        db.run(move |conn| {
+            dsl::files
+                .filter(dsl::rel_path.eq(rel_path))
+                .first::<File>(conn)
+                .ok()
+        })
+        .await
+    }
+
+    /// Returns all files whose relative path starts with the given prefix.
+    pub async fn by_rel_path_prefix(db: &FotoboekDatabase, prefix: &str) -> Vec<File> {
+        let pattern = format!("{}%", prefix);
+        let files: Vec<File> = db
+            .run(move |conn| {
+                dsl::files
+                    .filter(dsl::rel_path.like(pattern))
+                    .load(conn)
+                    .expect("Load files by rel_path prefix failed")
+            })
+            .await;
+
+        // LIKE treats "_" and "%" as wildcards, so filter out false positives
+        files
+            .into_iter()
+            .filter(|file| file.rel_path.starts_with(prefix))
+            .collect()
+    }
+
     pub async fn insert(self, db: &FotoboekDatabase) -> Result<Option<File>, String> {
         db.run(move |conn| {
             insert_into(dsl::files)
@@ -47,4 +99,38 @@ impl File {
         })
         .await
     }
+
+    pub async fn update(&self, db: &FotoboekDatabase) -> Result<(), String> {
+        let file_id = self.id;
+        let rel_path = self.rel_path.clone();
+        let file_name = self.file_name.clone();
+        db.run(move |conn| {
+            diesel::update(dsl::files.filter(dsl::id.eq(file_id)))
+                .set((dsl::rel_path.eq(rel_path), dsl::file_name.eq(file_name)))
+                .execute(conn)
+                .map_err(|err| err.to_string())?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Deletes the file together with its metadata and tasks. Foreign key constraints are not
+    /// enforced by SQLite by default, hence the dependent rows are deleted explicitly.
+    pub async fn delete(self, db: &FotoboekDatabase) -> Result<usize, String> {
+        db.run(move |conn| {
+            conn.immediate_transaction(|| {
+                diesel::delete(tasks::table.filter(tasks::file_id.eq(self.id.unwrap())))
+                    .execute(conn)?;
+                diesel::delete(
+                    perceptual_hashes::table.filter(perceptual_hashes::file_id.eq(self.id)),
+                )
+                .execute(conn)?;
+                diesel::delete(file_metadata::table.filter(file_metadata::file_id.eq(self.id)))
+                    .execute(conn)?;
+                diesel::delete(dsl::files.filter(dsl::id.eq(self.id))).execute(conn)
+            })
+            .map_err(|err: diesel::result::Error| err.to_string())
+        })
+        .await
+    }
 }
@@ -6,7 +6,7 @@ use crate::schema::file_metadata;
 use crate::schema::file_metadata::dsl;
 use crate::FotoboekDatabase;
 
-#[derive(Insertable, Queryable, QueryableByName, Serialize, Debug)]
+#[derive(Insertable, Queryable, QueryableByName, Serialize, Clone, Debug)]
 #[table_name = "file_metadata"]
 pub struct FileMetadata {
     pub file_id: Option<i32>,
@@ -24,10 +24,31 @@ pub struct FileMetadata {
     pub exif_gps_lat: Option<f32>,
     pub exif_gps_lon: Option<f32>,
     pub effective_date: NaiveDateTime,
-    pub filename_date: Option<NaiveDateTime>
+    pub filename_date: Option<NaiveDateTime>,
+    /// Duration of videos in seconds
+    pub video_duration: Option<i32>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub video_frame_rate: Option<f32>,
+    /// Clockwise rotation in degrees that is applied to videos when they are played
+    pub video_rotation: Option<i32>,
+    /// EXIF orientation (1-8) of images, which is applied to previews and the resolution
+    pub exif_orientation: Option<i32>,
+    /// Last modification date of the file, used to detect whether it changed since its metadata was
+    /// extracted
+    pub file_modified_at: Option<NaiveDateTime>,
 }
 
 impl FileMetadata {
+    pub async fn all(db: &FotoboekDatabase) -> Vec<FileMetadata> {
+        db.run(move |conn| {
+            dsl::file_metadata
+                .load::<FileMetadata>(conn)
+                .expect("Load all file_metadata failed")
+        })
+        .await
+    }
+
     pub async fn by_file_id(db: &FotoboekDatabase, file_id: i32) -> Option<FileMetadata> {
         db.run(move |conn| {
             dsl::file_metadata
@@ -38,6 +59,29 @@ impl FileMetadata {
         .await
     }
 
+    pub async fn by_file_ids(db: &FotoboekDatabase, file_ids: Vec<i32>) -> Vec<FileMetadata> {
+        db.run(move |conn| {
+            dsl::file_metadata
+                .filter(dsl::file_id.eq_any(file_ids))
+                .load::<FileMetadata>(conn)
+                .expect("Load file_metadata by file_ids failed")
+        })
+        .await
+    }
+
+    /// Returns the number of files that share the given content hash.
+    pub async fn count_by_file_hash(db: &FotoboekDatabase, file_hash: &str) -> i64 {
+        let file_hash = file_hash.to_string();
+        db.run(move |conn| {
+            dsl::file_metadata
+                .filter(dsl::file_hash.eq(file_hash))
+                .count()
+                .get_result(conn)
+                .expect("Count file_metadata by file_hash failed")
+        })
+        .await
+    }
+
     pub async fn save(self, db: &FotoboekDatabase) -> Result<(), String> {
         db.run(move |conn| {
             conn.immediate_transaction(|| {
@@ -1,7 +1,11 @@
 mod file;
 mod file_metadata;
+mod perceptual_hash;
+mod scan;
 mod task;
 
 pub use file::File;
 pub use file_metadata::FileMetadata;
+pub use perceptual_hash::PerceptualHash;
+pub use scan::Scan;
 pub use task::Task;
@@ -0,0 +1,39 @@
+use diesel::{self, prelude::*};
+use serde::Serialize;
+
+use crate::schema::perceptual_hashes;
+use crate::schema::perceptual_hashes::dsl;
+use crate::FotoboekDatabase;
+
+/// Perceptual hash of the preview of a file. Similar looking images have hashes with a small
+/// hamming distance.
+#[derive(Insertable, Queryable, Serialize, Debug)]
+#[table_name = "perceptual_hashes"]
+pub struct PerceptualHash {
+    pub file_id: Option<i32>,
+    /// Difference hash (dHash), the 64 bits are stored as signed integer
+    pub dhash: i64,
+}
+
+impl PerceptualHash {
+    pub async fn by_file_id(db: &FotoboekDatabase, file_id: i32) -> Option<PerceptualHash> {
+        db.run(move |conn| {
+            dsl::perceptual_hashes
+                .filter(dsl::file_id.eq(file_id))
+                .first::<PerceptualHash>(conn)
+                .ok()
+        })
+        .await
+    }
+
+    pub async fn save(self, db: &FotoboekDatabase) -> Result<(), String> {
+        db.run(move |conn| {
+            diesel::replace_into(dsl::perceptual_hashes)
+                .values(&self)
+                .execute(conn)
+                .map_err(|err| err.to_string())?;
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,144 @@
+use chrono::NaiveDateTime;
+use diesel::{self, prelude::*};
+use serde::Serialize;
+
+use crate::schema::scans;
+use crate::schema::scans::dsl;
+use crate::FotoboekDatabase;
+
+const STATUS_RUNNING: &str = "RUNNING";
+const STATUS_FINISHED: &str = "FINISHED";
+const STATUS_FAILED: &str = "FAILED";
+
+no_arg_sql_function!(last_insert_rowid, diesel::sql_types::Integer);
+
+#[derive(Insertable, Queryable, AsChangeset, Clone, Serialize, Debug)]
+#[table_name = "scans"]
+pub struct Scan {
+    pub id: Option<i32>,
+    pub triggered_by: String,
+    pub status: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub images_total: i32,
+    pub images_added: i32,
+    pub images_removed: i32,
+    pub videos_total: i32,
+    pub videos_added: i32,
+    pub videos_removed: i32,
+    pub errors: Option<String>,
+    pub images_modified: i32,
+    pub videos_modified: i32,
+    pub images_moved: i32,
+    pub videos_moved: i32,
+}
+
+impl Scan {
+    /// Returns a new, not yet persisted scan that started just now.
+    pub fn start(triggered_by: &str) -> Scan {
+        Scan {
+            id: None,
+            triggered_by: triggered_by.into(),
+            status: STATUS_RUNNING.into(),
+            started_at: chrono::Utc::now().naive_utc(),
+            finished_at: None,
+            images_total: 0,
+            images_added: 0,
+            images_removed: 0,
+            videos_total: 0,
+            videos_added: 0,
+            videos_removed: 0,
+            errors: None,
+            images_modified: 0,
+            videos_modified: 0,
+            images_moved: 0,
+            videos_moved: 0,
+        }
+    }
+
+    /// Marks the scan as finished just now.
+    pub fn finish(&mut self) {
+        self.status = STATUS_FINISHED.into();
+        self.finished_at = Some(chrono::Utc::now().naive_utc());
+    }
+
+    /// Marks all running scans as failed with the given error, e.g. because they were interrupted
+    /// by a restart. Returns the number of failed scans.
+    pub async fn fail_running(db: &FotoboekDatabase, error: &str) -> Result<usize, String> {
+        let error = error.to_string();
+        db.run(move |conn| {
+            diesel::update(dsl::scans.filter(dsl::status.eq(STATUS_RUNNING)))
+                .set((
+                    dsl::status.eq(STATUS_FAILED),
+                    dsl::finished_at.eq(chrono::Utc::now().naive_utc()),
+                    dsl::errors.eq(error),
+                ))
+                .execute(conn)
+                .map_err(|err| err.to_string())
+        })
+        .await
+    }
+
+    /// Returns the most recent running scan, if any.
+    pub async fn latest_running(db: &FotoboekDatabase) -> Option<Scan> {
+        db.run(move |conn| {
+            dsl::scans
+                .filter(dsl::status.eq(STATUS_RUNNING))
+                .order(dsl::id.desc())
+                .first::<Scan>(conn)
+                .ok()
+        })
+        .await
+    }
+
+    /// Returns all scans, the most recent first.
+    pub async fn all(db: &FotoboekDatabase) -> Vec<Scan> {
+        db.run(move |conn| {
+            dsl::scans
+                .order(dsl::id.desc())
+                .load::<Scan>(conn)
+                .expect("Query scans failed")
+        })
+        .await
+    }
+
+    pub async fn by_id(db: &FotoboekDatabase, scan_id: i32) -> Option<Scan> {
+        db.run(move |conn| {
+            dsl::scans
+                .filter(dsl::id.eq(scan_id))
+                .first::<Scan>(conn)
+                .ok()
+        })
+        .await
+    }
+
+    /// Inserts the scan and returns it including its newly assigned id.
+    pub async fn insert(self, db: &FotoboekDatabase) -> Result<Scan, String> {
+        db.run(move |conn| {
+            conn.immediate_transaction(|| {
+                diesel::insert_into(dsl::scans)
+                    .values(&self)
+                    .execute(conn)?;
+                let id = diesel::select(last_insert_rowid).get_result::<i32>(conn)?;
+                Ok(Scan {
+                    id: Some(id),
+                    ..self
+                })
+            })
+            .map_err(|err: diesel::result::Error| err.to_string())
+        })
+        .await
+    }
+
+    pub async fn update(&self, db: &FotoboekDatabase) -> Result<(), String> {
+        let scan = self.clone();
+        db.run(move |conn| {
+            diesel::update(dsl::scans.filter(dsl::id.eq(scan.id)))
+                .set(&scan)
+                .execute(conn)
+                .map_err(|err| err.to_string())?;
+            Ok(())
+        })
+        .await
+    }
+}
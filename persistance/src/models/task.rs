@@ -1,3 +1,5 @@
+use diesel::dsl::sql;
+use diesel::sql_types::{Bool, Integer, Text, Timestamp};
 use diesel::{self, prelude::*};
 use log::debug;
 use serde::Serialize;
@@ -8,6 +10,37 @@ use crate::schema::tasks;
 use crate::schema::tasks::dsl;
 use crate::FotoboekDatabase;
 
+/// Tasks are only workable once the task of the module they depend on is done, i.e. deleted.
+const PREREQUISITE_DONE_SQL: &str = "NOT EXISTS (
+    SELECT 1 FROM tasks AS prerequisite
+    WHERE prerequisite.file_id = tasks.file_id AND prerequisite.module = tasks.depends_on_module
+)";
+
+/// Error of tasks that are never run, because the task they depend on failed.
+pub const PREREQUISITE_FAILED_ERROR: &str = "Prerequisite failed";
+
+/// Marks the tasks of a file as failed, whose prerequisite failed, directly or by its own failed
+/// prerequisite. Binds the file id twice, the time of failure and the error.
+const FAIL_BLOCKED_TASKS_SQL: &str = "
+    WITH RECURSIVE blocked(id, module) AS (
+        SELECT task.id, task.module
+        FROM tasks AS task
+        INNER JOIN tasks AS prerequisite
+            ON prerequisite.file_id = task.file_id
+            AND prerequisite.module = task.depends_on_module
+        WHERE task.file_id = ? AND prerequisite.failed_at IS NOT NULL
+        UNION
+        SELECT task.id, task.module
+        FROM tasks AS task
+        INNER JOIN blocked
+            ON task.depends_on_module = blocked.module
+        WHERE task.file_id = ?
+    )
+    UPDATE tasks
+    SET failed_at = ?, last_error = ?
+    WHERE id IN (SELECT id FROM blocked) AND failed_at IS NULL
+";
+
 #[derive(Insertable, Queryable, Clone, Serialize, Debug)]
 pub struct Task {
     pub id: Option<i32>,
@@ -15,32 +48,71 @@ pub struct Task {
     pub module: String,
     pub priority: i32,
     pub work_started_at: chrono::NaiveDateTime,
-    pub max_worker_id: i32,
+    /// Number of failed runs
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    /// The task is not run again before this time after it failed
+    pub next_attempt_at: chrono::NaiveDateTime,
+    /// Set once the task failed `task_max_attempts` times, failed tasks are not run anymore
+    pub failed_at: Option<chrono::NaiveDateTime>,
+    /// Module of the task of the same file that must be done before this task is run
+    pub depends_on_module: Option<String>,
+    /// The worker that locked the task, only it may renew the lock
+    pub locked_by: Option<String>,
 }
 
 impl Task {
+    pub fn new(file_id: i32, module: &str, priority: i32) -> Task {
+        Task {
+            id: None,
+            file_id,
+            module: module.into(),
+            priority,
+            work_started_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+            failed_at: None,
+            depends_on_module: None,
+            locked_by: None,
+        }
+    }
+
+    pub fn depends_on(self, module: &str) -> Task {
+        Task {
+            depends_on_module: Some(module.into()),
+            ..self
+        }
+    }
+
     pub async fn all(db: &FotoboekDatabase) -> Vec<Task> {
         db.run(move |conn| dsl::tasks.load::<Task>(conn).expect("Query tasks failed"))
             .await
     }
 
+    /// Locks the next workable task of the given modules for the worker, identified by the lock
+    /// owner. Tasks locked by other workers are only handed out once their lock expired, i.e. their
+    /// worker stopped renewing it.
     pub async fn next_workable_by_priority_and_lock(
         db: &FotoboekDatabase,
         config: &FotoboekConfig,
-        worker_id: usize,
+        modules: &[String],
+        lock_owner: &str,
     ) -> Option<Task> {
         let dt_now = chrono::Utc::now().naive_utc();
-        let dt_one_hour_ago = chrono::NaiveDateTime::from_timestamp(
-            dt_now.timestamp() - config.task_lock_timeout_sec as i64,
-            0,
-        );
+        let modules = modules.to_vec();
+        let lock_owner = lock_owner.to_string();
+        let dt_one_hour_ago = lock_expired_before(config, dt_now);
 
         db.run(move |conn| loop {
             let workable_tasks: Vec<Task> = dsl::tasks
                 .filter(
                     dsl::work_started_at
                         .le(dt_one_hour_ago)
-                        .and(dsl::max_worker_id.ge(worker_id as i32)),
+                        .and(dsl::module.eq_any(&modules))
+                        .and(dsl::next_attempt_at.le(dt_now))
+                        .and(dsl::failed_at.is_null())
+                        .and(sql::<Bool>(PREREQUISITE_DONE_SQL)),
                 )
                 .order(dsl::priority.asc())
                 .limit(1)
@@ -50,7 +122,10 @@ impl Task {
             if workable_tasks.len() == 1 {
                 let task = workable_tasks.get(0).unwrap();
                 let success = diesel::update(dsl::tasks)
-                    .set(dsl::work_started_at.eq(dt_now))
+                    .set((
+                        dsl::work_started_at.eq(dt_now),
+                        dsl::locked_by.eq(&lock_owner),
+                    ))
                     .filter(
                         dsl::id
                             .eq(task.id)
@@ -60,7 +135,11 @@ impl Task {
                     .expect("Lock task update failed")
                     == 1;
                 if success {
-                    return Some(task.to_owned());
+                    return Some(Task {
+                        work_started_at: dt_now,
+                        locked_by: Some(lock_owner),
+                        ..task.to_owned()
+                    });
                 } else {
                     debug!(
                         "Task {} locked by now, looking fo the next...",
@@ -74,6 +153,174 @@ impl Task {
         .await
     }
 
+    /// Extends the lock of a task that is still being worked on, so no other worker picks it up.
+    /// Returns false if the lock was lost, because it expired and another worker locked the task.
+    pub async fn renew_lock(
+        db: &FotoboekDatabase,
+        task_id: i32,
+        lock_owner: &str,
+    ) -> Result<bool, String> {
+        let dt_now = chrono::Utc::now().naive_utc();
+        let lock_owner = lock_owner.to_string();
+        db.run(move |conn| {
+            diesel::update(dsl::tasks)
+                .set(dsl::work_started_at.eq(dt_now))
+                .filter(dsl::id.eq(task_id).and(dsl::locked_by.eq(lock_owner)))
+                .execute(conn)
+                .map(|updated_count| updated_count == 1)
+                .map_err(|err| err.to_string())
+        })
+        .await
+    }
+
+    pub async fn by_id(db: &FotoboekDatabase, task_id: i32) -> Option<Task> {
+        db.run(move |conn| {
+            dsl::tasks
+                .filter(dsl::id.eq(task_id))
+                .first::<Task>(conn)
+                .ok()
+        })
+        .await
+    }
+
+    /// Returns the tasks that failed too often, most recently failed first.
+    pub async fn failed(db: &FotoboekDatabase) -> Vec<Task> {
+        db.run(move |conn| {
+            dsl::tasks
+                .filter(dsl::failed_at.is_not_null())
+                .order(dsl::failed_at.desc())
+                .load::<Task>(conn)
+                .expect("Query tasks failed")
+        })
+        .await
+    }
+
+    pub async fn by_file_id_and_module(
+        db: &FotoboekDatabase,
+        file_id: i32,
+        module: &str,
+    ) -> Option<Task> {
+        let module = module.to_string();
+        db.run(move |conn| {
+            dsl::tasks
+                .filter(dsl::file_id.eq(file_id).and(dsl::module.eq(module)))
+                .first::<Task>(conn)
+                .ok()
+        })
+        .await
+    }
+
+    /// Returns true if a worker is currently working on the task, i.e. it locked the task and the
+    /// lock did not time out yet.
+    pub fn is_locked(&self, config: &FotoboekConfig) -> bool {
+        let dt_now = chrono::Utc::now().naive_utc();
+        self.work_started_at > lock_expired_before(config, dt_now)
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.failed_at.is_some()
+    }
+
+    /// Returns true if the task failed recently and waits for its next attempt.
+    pub fn is_backing_off(&self) -> bool {
+        self.attempts > 0 && self.next_attempt_at > chrono::Utc::now().naive_utc()
+    }
+
+    /// Unlocks the task after a failed run. The task is run again after an exponential backoff,
+    /// unless it reached the maximum number of attempts, then it is marked as failed together with
+    /// the tasks depending on it. Returns None if the worker lost the lock to another worker, which
+    /// then records the outcome itself.
+    pub async fn record_failure(
+        self,
+        db: &FotoboekDatabase,
+        config: &FotoboekConfig,
+        lock_owner: &str,
+        error: &str,
+    ) -> Result<Option<Task>, String> {
+        let dt_now = chrono::Utc::now().naive_utc();
+        let file_id = self.file_id;
+        let attempts = self.attempts + 1;
+        let failed_at = if attempts as usize >= config.task_max_attempts {
+            Some(dt_now)
+        } else {
+            None
+        };
+        let retry_delay = retry_delay_sec(config.task_retry_backoff_sec, attempts);
+        let task = Task {
+            work_started_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+            locked_by: None,
+            attempts,
+            last_error: Some(error.to_string()),
+            next_attempt_at: dt_now + chrono::Duration::seconds(retry_delay),
+            failed_at,
+            ..self
+        };
+        let task = task
+            .update_attempt_state(db, Some(lock_owner.to_string()))
+            .await?;
+        if matches!(&task, Some(task) if task.is_failed()) {
+            db.run(move |conn| fail_blocked_tasks(conn, file_id, dt_now))
+                .await
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(task)
+    }
+
+    /// Makes a failed task workable again, as if it was never run, and with it the tasks that
+    /// failed because they depend on it.
+    pub async fn retry(self, db: &FotoboekDatabase) -> Result<Task, String> {
+        let file_id = self.file_id;
+        let task = Task {
+            work_started_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+            locked_by: None,
+            attempts: 0,
+            last_error: None,
+            next_attempt_at: chrono::NaiveDateTime::from_timestamp(0, 0),
+            failed_at: None,
+            ..self
+        };
+        let task = task
+            .update_attempt_state(db, None)
+            .await?
+            .ok_or_else(|| "Task not found".to_string())?;
+        unblock_dependent_tasks(db, file_id).await?;
+        Ok(task)
+    }
+
+    /// Deletes a failed task, the tasks that failed because they depend on it are run anyway.
+    pub async fn discard(self, db: &FotoboekDatabase) -> Result<(), String> {
+        let file_id = self.file_id;
+        self.delete(db).await?;
+        unblock_dependent_tasks(db, file_id).await
+    }
+
+    /// Stores the attempts of the task. If a lock owner is given, the task is only updated if it
+    /// is still locked by that owner. Returns None if the task was not updated.
+    async fn update_attempt_state(
+        self,
+        db: &FotoboekDatabase,
+        lock_owner: Option<String>,
+    ) -> Result<Option<Task>, String> {
+        db.run(move |conn| {
+            let mut query = diesel::update(dsl::tasks.filter(dsl::id.eq(self.id)))
+                .set((
+                    dsl::work_started_at.eq(self.work_started_at),
+                    dsl::locked_by.eq(&self.locked_by),
+                    dsl::attempts.eq(self.attempts),
+                    dsl::last_error.eq(&self.last_error),
+                    dsl::next_attempt_at.eq(self.next_attempt_at),
+                    dsl::failed_at.eq(self.failed_at),
+                ))
+                .into_boxed();
+            if let Some(lock_owner) = lock_owner {
+                query = query.filter(dsl::locked_by.eq(lock_owner));
+            }
+            let updated_count = query.execute(conn).map_err(|err| err.to_string())?;
+            Ok(if updated_count == 1 { Some(self) } else { None })
+        })
+        .await
+    }
+
     pub async fn insert(self, db: &FotoboekDatabase) -> Result<(), String> {
         db.run(move |conn| {
             diesel::insert_into(dsl::tasks)
@@ -93,4 +340,213 @@ impl Task {
         })
         .await
     }
+
+    /// Deletes the task, unless a worker is working on it, which then deletes it itself once done.
+    /// Returns false in that case.
+    pub async fn delete_if_unlocked(
+        self,
+        db: &FotoboekDatabase,
+        config: &FotoboekConfig,
+    ) -> Result<bool, String> {
+        let lock_expired_before = lock_expired_before(config, chrono::Utc::now().naive_utc());
+        db.run(move |conn| {
+            diesel::delete(
+                dsl::tasks.filter(
+                    dsl::id
+                        .eq(self.id)
+                        .and(dsl::work_started_at.le(lock_expired_before)),
+                ),
+            )
+            .execute(conn)
+            .map(|deleted_count| deleted_count == 1)
+            .map_err(|err| err.to_string())
+        })
+        .await
+    }
+
+    /// Deletes the finished task, unless the worker lost the lock to another worker, which then
+    /// finishes the task itself. Returns false in that case.
+    pub async fn delete_if_locked_by(
+        self,
+        db: &FotoboekDatabase,
+        lock_owner: &str,
+    ) -> Result<bool, String> {
+        let lock_owner = lock_owner.to_string();
+        db.run(move |conn| {
+            diesel::delete(
+                dsl::tasks.filter(dsl::id.eq(self.id).and(dsl::locked_by.eq(lock_owner))),
+            )
+            .execute(conn)
+            .map(|deleted_count| deleted_count == 1)
+            .map_err(|err| err.to_string())
+        })
+        .await
+    }
+
+    pub async fn delete_by_file_id(db: &FotoboekDatabase, file_id: i32) -> Result<usize, String> {
+        db.run(move |conn| {
+            diesel::delete(dsl::tasks.filter(dsl::file_id.eq(file_id)))
+                .execute(conn)
+                .map_err(|err| err.to_string())
+        })
+        .await
+    }
+}
+
+fn fail_blocked_tasks(
+    conn: &SqliteConnection,
+    file_id: i32,
+    dt_now: chrono::NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::sql_query(FAIL_BLOCKED_TASKS_SQL)
+        .bind::<Integer, _>(file_id)
+        .bind::<Integer, _>(file_id)
+        .bind::<Timestamp, _>(dt_now)
+        .bind::<Text, _>(PREREQUISITE_FAILED_ERROR)
+        .execute(conn)
+}
+
+/// Makes the tasks of the file workable again that failed because of a failed prerequisite,
+/// unless another of their prerequisites is still failed.
+fn reset_blocked_tasks(
+    conn: &SqliteConnection,
+    file_id: i32,
+    dt_now: chrono::NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::update(
+        dsl::tasks.filter(
+            dsl::file_id
+                .eq(file_id)
+                .and(dsl::failed_at.is_not_null())
+                .and(dsl::last_error.eq(PREREQUISITE_FAILED_ERROR)),
+        ),
+    )
+    .set((
+        dsl::failed_at.eq(None::<chrono::NaiveDateTime>),
+        dsl::last_error.eq(None::<String>),
+    ))
+    .execute(conn)?;
+    fail_blocked_tasks(conn, file_id, dt_now)
+}
+
+async fn unblock_dependent_tasks(db: &FotoboekDatabase, file_id: i32) -> Result<(), String> {
+    let dt_now = chrono::Utc::now().naive_utc();
+    db.run(move |conn| reset_blocked_tasks(conn, file_id, dt_now))
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+/// Tasks locked before the returned time are considered abandoned and may be locked again.
+fn lock_expired_before(
+    config: &FotoboekConfig,
+    dt_now: chrono::NaiveDateTime,
+) -> chrono::NaiveDateTime {
+    chrono::NaiveDateTime::from_timestamp(
+        dt_now.timestamp() - config.task_lock_timeout_sec as i64,
+        0,
+    )
+}
+
+/// Seconds to wait before the next attempt, doubled with every failed attempt.
+fn retry_delay_sec(backoff_sec: usize, attempts: i32) -> i64 {
+    let exponent = (attempts - 1).clamp(0, 16) as u32;
+    backoff_sec as i64 * 2_i64.pow(exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_doubles_with_every_attempt() {
+        assert_eq!(60, retry_delay_sec(60, 1));
+        assert_eq!(120, retry_delay_sec(60, 2));
+        assert_eq!(480, retry_delay_sec(60, 4));
+        assert_eq!(60 * 65536, retry_delay_sec(60, 100));
+    }
+
+    fn connection_with_tasks(tasks: Vec<Task>) -> SqliteConnection {
+        let conn = SqliteConnection::establish(":memory:").unwrap();
+        let migrations_dir = format!("{}/../migrations", env!("CARGO_MANIFEST_DIR"));
+        diesel_migrations::run_pending_migrations_in_directory(
+            &conn,
+            std::path::Path::new(&migrations_dir),
+            &mut std::io::sink(),
+        )
+        .unwrap();
+        diesel::insert_into(dsl::tasks)
+            .values(&tasks)
+            .execute(&conn)
+            .unwrap();
+        conn
+    }
+
+    fn tasks_of_file(file_id: i32) -> Vec<Task> {
+        vec![
+            Task::new(file_id, "metadata", 100),
+            Task::new(file_id, "preview", 200).depends_on("metadata"),
+            Task::new(file_id, "phash", 400).depends_on("preview"),
+        ]
+    }
+
+    fn set_failed(conn: &SqliteConnection, file_id: i32, module: &str, failed: bool) {
+        let failed_at = if failed {
+            Some(chrono::Utc::now().naive_utc())
+        } else {
+            None
+        };
+        diesel::update(dsl::tasks.filter(dsl::file_id.eq(file_id).and(dsl::module.eq(module))))
+            .set((
+                dsl::failed_at.eq(failed_at),
+                dsl::last_error.eq(failed_at.map(|_| "Error".to_string())),
+            ))
+            .execute(conn)
+            .unwrap();
+    }
+
+    fn failed_modules(conn: &SqliteConnection) -> Vec<(i32, String, Option<String>)> {
+        dsl::tasks
+            .filter(dsl::failed_at.is_not_null())
+            .order((dsl::file_id, dsl::priority))
+            .load::<Task>(conn)
+            .unwrap()
+            .into_iter()
+            .map(|task| (task.file_id, task.module, task.last_error))
+            .collect()
+    }
+
+    #[test]
+    fn tasks_depending_on_failed_tasks_fail() {
+        let conn = connection_with_tasks([tasks_of_file(1), tasks_of_file(2)].concat());
+        set_failed(&conn, 1, "metadata", true);
+
+        let dt_now = chrono::Utc::now().naive_utc();
+        assert_eq!(2, fail_blocked_tasks(&conn, 1, dt_now).unwrap());
+        let blocked = Some(PREREQUISITE_FAILED_ERROR.to_string());
+        assert_eq!(
+            vec![
+                (1, "metadata".to_string(), Some("Error".to_string())),
+                (1, "preview".to_string(), blocked.clone()),
+                (1, "phash".to_string(), blocked),
+            ],
+            failed_modules(&conn)
+        );
+    }
+
+    #[test]
+    fn blocked_tasks_are_reset_once_prerequisites_are_retried() {
+        let conn = connection_with_tasks(tasks_of_file(1));
+        set_failed(&conn, 1, "preview", true);
+        let dt_now = chrono::Utc::now().naive_utc();
+        fail_blocked_tasks(&conn, 1, dt_now).unwrap();
+
+        // The phash task still depends on the failed preview task
+        reset_blocked_tasks(&conn, 1, dt_now).unwrap();
+        assert_eq!(2, failed_modules(&conn).len());
+
+        set_failed(&conn, 1, "preview", false);
+        reset_blocked_tasks(&conn, 1, dt_now).unwrap();
+        assert!(failed_modules(&conn).is_empty());
+    }
 }
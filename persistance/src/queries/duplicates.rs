@@ -0,0 +1,145 @@
+use crate::diesel::RunQueryDsl;
+use crate::FotoboekDatabase;
+use chrono::NaiveDateTime;
+use diesel::sql_types::{Integer, Text, Timestamp};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DuplicateFileInfo {
+    pub id: i32,
+    pub rel_path: String,
+    pub file_type: String,
+    pub file_size_bytes: i32,
+    pub file_date: NaiveDateTime,
+    pub effective_date: NaiveDateTime,
+}
+
+/// Files with identical contents, grouped by their content hash.
+pub type DuplicateGroups = BTreeMap<String, Vec<DuplicateFileInfo>>;
+
+/// SQL condition that keeps only one file (the one with the lowest id) of each group of
+/// duplicates.
+pub const UNIQUE_FILES_CONDITION: &str = r#"
+    files.id IN (
+        SELECT MIN(file_metadata.file_id)
+        FROM file_metadata
+        GROUP BY file_metadata.file_hash
+    )
+"#;
+
+#[derive(QueryableByName, Debug)]
+struct DuplicateFile {
+    #[sql_type = "Text"]
+    file_hash: String,
+    #[sql_type = "Integer"]
+    file_id: i32,
+    #[sql_type = "Text"]
+    rel_path: String,
+    #[sql_type = "Text"]
+    file_type: String,
+    #[sql_type = "Integer"]
+    file_size_bytes: i32,
+    #[sql_type = "Timestamp"]
+    file_date: NaiveDateTime,
+    #[sql_type = "Timestamp"]
+    effective_date: NaiveDateTime,
+}
+
+/// Returns all files that share their contents with at least one other file.
+pub async fn groups(db: &FotoboekDatabase) -> DuplicateGroups {
+    let duplicate_files: Vec<DuplicateFile> = db
+        .run(move |conn| {
+            let sql = r#"
+            SELECT
+                file_metadata.file_hash AS file_hash,
+                files.id AS file_id,
+                files.rel_path AS rel_path,
+                files.file_type AS file_type,
+                file_metadata.file_size_bytes AS file_size_bytes,
+                file_metadata.file_date AS file_date,
+                file_metadata.effective_date AS effective_date
+            FROM files
+            INNER JOIN file_metadata
+                ON files.id = file_metadata.file_id
+            WHERE file_metadata.file_hash IN (
+                SELECT file_hash
+                FROM file_metadata
+                GROUP BY file_hash
+                HAVING COUNT(file_id) > 1
+            )
+            ORDER BY files.rel_path
+        "#;
+
+            diesel::sql_query(sql)
+                .load(conn)
+                .expect("Query duplicates.groups failed")
+        })
+        .await;
+
+    map_to_duplicate_groups(duplicate_files)
+}
+
+fn map_to_duplicate_groups(duplicate_files: Vec<DuplicateFile>) -> DuplicateGroups {
+    duplicate_files
+        .into_iter()
+        .fold(BTreeMap::new(), |mut map, it| {
+            let entry = map.entry(it.file_hash).or_insert(Vec::new());
+            entry.push(DuplicateFileInfo {
+                id: it.file_id,
+                rel_path: it.rel_path,
+                file_type: it.file_type,
+                file_size_bytes: it.file_size_bytes,
+                file_date: it.file_date,
+                effective_date: it.effective_date,
+            });
+            map
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn duplicate_file(file_hash: &str, file_id: i32, rel_path: &str) -> DuplicateFile {
+        let date = NaiveDate::from_ymd(2022, 2, 19).and_hms(10, 0, 0);
+        DuplicateFile {
+            file_hash: file_hash.to_string(),
+            file_id,
+            rel_path: rel_path.to_string(),
+            file_type: "IMAGE".to_string(),
+            file_size_bytes: 100,
+            file_date: date,
+            effective_date: date,
+        }
+    }
+
+    #[test]
+    fn no_duplicates() {
+        assert_eq!(0, map_to_duplicate_groups(vec![]).len());
+    }
+
+    #[test]
+    fn files_grouped_by_hash() {
+        let groups = map_to_duplicate_groups(vec![
+            duplicate_file("aaa", 1, "backup/image1.jpg"),
+            duplicate_file("bbb", 2, "backup/image2.jpg"),
+            duplicate_file("aaa", 3, "image1.jpg"),
+            duplicate_file("bbb", 4, "whatsapp/image2.jpg"),
+            duplicate_file("bbb", 5, "image2.jpg"),
+        ]);
+        assert_eq!(2, groups.len());
+
+        let group = groups.get("aaa").unwrap();
+        assert_eq!(vec![1, 3], group.iter().map(|it| it.id).collect::<Vec<_>>());
+        assert_eq!("backup/image1.jpg", group[0].rel_path);
+        assert_eq!("image1.jpg", group[1].rel_path);
+
+        let group = groups.get("bbb").unwrap();
+        assert_eq!(
+            vec![2, 4, 5],
+            group.iter().map(|it| it.id).collect::<Vec<_>>()
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use crate::diesel::RunQueryDsl;
+use crate::queries::duplicates::UNIQUE_FILES_CONDITION;
 use crate::FotoboekDatabase;
 use chrono::{Datelike, Local};
-use diesel::sql_types::{Integer, Text};
+use diesel::sql_types::{Integer, Nullable, Text};
 use serde::Serialize;
 use std::collections::BTreeMap;
 
@@ -9,10 +10,16 @@ use std::collections::BTreeMap;
 pub struct FlashbackFileInfo {
     id: i32,
     r#type: String,
+    /// Hash of the file contents, the version of its preview and video URLs
+    file_hash: String,
+    /// Duration of videos in seconds
+    video_duration: Option<i32>,
 }
 pub type FlashbackDates = BTreeMap<String, Vec<FlashbackFileInfo>>;
 
-pub async fn dates(db: FotoboekDatabase) -> FlashbackDates {
+/// Returns the files by date. If `hide_duplicates` is set, only one file of files with identical
+/// contents is returned.
+pub async fn dates(db: FotoboekDatabase, hide_duplicates: bool) -> FlashbackDates {
     #[derive(QueryableByName)]
     struct ImageDate {
         #[sql_type = "Text"]
@@ -21,6 +28,10 @@ pub async fn dates(db: FotoboekDatabase) -> FlashbackDates {
         file_id: i32,
         #[sql_type = "Text"]
         file_type: String,
+        #[sql_type = "Text"]
+        file_hash: String,
+        #[sql_type = "Nullable<Integer>"]
+        video_duration: Option<i32>,
     }
 
     db.run(move |conn| {
@@ -28,16 +39,22 @@ pub async fn dates(db: FotoboekDatabase) -> FlashbackDates {
         let day = date.day() as i32;
         let month = date.month() as i32;
 
-        let sql = r#"
+        let mut sql = r#"
             SELECT
                 DATE(file_metadata.effective_date) as date,
                 files.id AS file_id,
-                files.file_type AS file_type
+                files.file_type AS file_type,
+                file_metadata.file_hash AS file_hash,
+                file_metadata.video_duration AS video_duration
             FROM files
             INNER JOIN file_metadata
                 ON files.id = file_metadata.file_id
             WHERE STRFTIME('%m-%d', effective_date) = ?
-        "#;
+        "#
+        .to_string();
+        if hide_duplicates {
+            sql = format!("{} AND {}", sql, UNIQUE_FILES_CONDITION);
+        }
 
         let image_dates: Vec<ImageDate> = diesel::sql_query(sql)
             .bind::<diesel::sql_types::Text, _>(format!("{:0>2}-{:0>2}", month, day))
@@ -49,6 +66,8 @@ pub async fn dates(db: FotoboekDatabase) -> FlashbackDates {
             entry.push(FlashbackFileInfo {
                 id: it.file_id,
                 r#type: it.file_type.clone(),
+                file_hash: it.file_hash.clone(),
+                video_duration: it.video_duration,
             });
             return map;
         })
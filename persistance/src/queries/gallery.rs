@@ -1,7 +1,7 @@
 use crate::diesel::RunQueryDsl;
 use crate::FotoboekDatabase;
 use chrono::NaiveDateTime;
-use diesel::sql_types::{Integer, Text, Timestamp};
+use diesel::sql_types::{Integer, Nullable, Text, Timestamp};
 
 #[derive(QueryableByName)]
 pub struct GalleryFileInfo {
@@ -13,8 +13,12 @@ pub struct GalleryFileInfo {
     pub file_name: String,
     #[sql_type = "Text"]
     pub file_type: String,
+    #[sql_type = "Text"]
+    pub file_hash: String,
     #[sql_type = "Timestamp"]
     pub effective_date: NaiveDateTime,
+    #[sql_type = "Nullable<Integer>"]
+    pub video_duration: Option<i32>,
 }
 
 pub async fn get_gallery_file_infos(db: &FotoboekDatabase) -> Vec<GalleryFileInfo> {
@@ -25,7 +29,9 @@ pub async fn get_gallery_file_infos(db: &FotoboekDatabase) -> Vec<GalleryFileInf
                 files.rel_path AS rel_path,
                 files.file_name AS file_name,
                 files.file_type AS file_type,
-                file_metadata.effective_date AS effective_date
+                file_metadata.file_hash AS file_hash,
+                file_metadata.effective_date AS effective_date,
+                file_metadata.video_duration AS video_duration
             FROM files
             INNER JOIN file_metadata
                 ON files.id = file_metadata.file_id
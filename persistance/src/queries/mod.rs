@@ -1,4 +1,6 @@
 pub mod admin;
+pub mod duplicates;
 pub mod flashback;
 pub mod gallery;
+pub mod similarity;
 pub mod timeline;
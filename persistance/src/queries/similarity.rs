@@ -0,0 +1,252 @@
+use crate::diesel::RunQueryDsl;
+use crate::FotoboekDatabase;
+use chrono::NaiveDateTime;
+use diesel::sql_types::{BigInt, Integer, Text, Timestamp};
+use diesel::SqliteConnection;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SimilarFileInfo {
+    pub id: i32,
+    pub rel_path: String,
+    pub file_type: String,
+    pub effective_date: NaiveDateTime,
+    /// Hamming distance of the perceptual hashes, 0 means the previews look identical
+    pub distance: u32,
+}
+
+#[derive(QueryableByName, Debug)]
+struct FileHash {
+    #[sql_type = "Integer"]
+    file_id: i32,
+    #[sql_type = "Text"]
+    rel_path: String,
+    #[sql_type = "Text"]
+    file_type: String,
+    #[sql_type = "Timestamp"]
+    effective_date: NaiveDateTime,
+    #[sql_type = "BigInt"]
+    dhash: i64,
+}
+
+/// Returns all files that look similar to the given file, the most similar first.
+pub async fn similar_files(
+    db: &FotoboekDatabase,
+    file_id: i32,
+    max_distance: u32,
+) -> Vec<SimilarFileInfo> {
+    db.run(move |conn| {
+        let file_hashes = load_file_hashes(conn);
+        let dhash = match file_hashes.iter().find(|it| it.file_id == file_id) {
+            Some(file_hash) => file_hash.dhash,
+            None => return vec![],
+        };
+
+        let mut similar_files: Vec<SimilarFileInfo> = file_hashes
+            .iter()
+            .filter(|it| it.file_id != file_id)
+            .map(|it| to_similar_file_info(it, hamming_distance(dhash, it.dhash)))
+            .filter(|it| it.distance <= max_distance)
+            .collect();
+        similar_files.sort_by_key(|it| it.distance);
+        similar_files
+    })
+    .await
+}
+
+/// Returns clusters of files that look similar to each other, e.g. resized copies or burst shots.
+/// If `max_seconds_apart` is given, only files taken within that time span are clustered, which
+/// finds bursts of shots. Clustering runs on the blocking database thread, as it compares many
+/// pairs of files.
+pub async fn clusters(
+    db: &FotoboekDatabase,
+    max_distance: u32,
+    max_seconds_apart: Option<i64>,
+) -> Vec<Vec<SimilarFileInfo>> {
+    db.run(move |conn| {
+        let file_hashes = load_file_hashes(conn);
+        cluster_indices(&file_hashes, max_distance, max_seconds_apart)
+            .into_iter()
+            .map(|indices| {
+                let first_dhash = file_hashes[indices[0]].dhash;
+                indices
+                    .into_iter()
+                    .map(|index| {
+                        let file_hash = &file_hashes[index];
+                        let distance = hamming_distance(first_dhash, file_hash.dhash);
+                        to_similar_file_info(file_hash, distance)
+                    })
+                    .collect()
+            })
+            .collect()
+    })
+    .await
+}
+
+fn load_file_hashes(conn: &SqliteConnection) -> Vec<FileHash> {
+    let sql = r#"
+        SELECT
+            files.id AS file_id,
+            files.rel_path AS rel_path,
+            files.file_type AS file_type,
+            file_metadata.effective_date AS effective_date,
+            perceptual_hashes.dhash AS dhash
+        FROM files
+        INNER JOIN file_metadata
+            ON files.id = file_metadata.file_id
+        INNER JOIN perceptual_hashes
+            ON files.id = perceptual_hashes.file_id
+        ORDER BY file_metadata.effective_date
+    "#;
+
+    diesel::sql_query(sql)
+        .load(conn)
+        .expect("Query similarity.load_file_hashes failed")
+}
+
+fn to_similar_file_info(file_hash: &FileHash, distance: u32) -> SimilarFileInfo {
+    SimilarFileInfo {
+        id: file_hash.file_id,
+        rel_path: file_hash.rel_path.clone(),
+        file_type: file_hash.file_type.clone(),
+        effective_date: file_hash.effective_date,
+        distance,
+    }
+}
+
+fn hamming_distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Groups the file hashes, which must be ordered by date, into clusters of similar files. Returns
+/// the indices of the files of each cluster with more than one file.
+fn cluster_indices(
+    file_hashes: &[FileHash],
+    max_distance: u32,
+    max_seconds_apart: Option<i64>,
+) -> Vec<Vec<usize>> {
+    fn find_root(parents: &mut [usize], mut index: usize) -> usize {
+        while parents[index] != index {
+            parents[index] = parents[parents[index]];
+            index = parents[index];
+        }
+        index
+    }
+
+    let mut parents: Vec<usize> = (0..file_hashes.len()).collect();
+    for bucket in candidate_buckets(file_hashes, max_distance) {
+        for (position, &i) in bucket.iter().enumerate() {
+            for &j in bucket[(position + 1)..].iter() {
+                if let Some(max_seconds_apart) = max_seconds_apart {
+                    let seconds_apart = file_hashes[j].effective_date.timestamp()
+                        - file_hashes[i].effective_date.timestamp();
+                    if seconds_apart > max_seconds_apart {
+                        break;
+                    }
+                }
+                if hamming_distance(file_hashes[i].dhash, file_hashes[j].dhash) <= max_distance {
+                    let root_i = find_root(&mut parents, i);
+                    let root_j = find_root(&mut parents, j);
+                    parents[root_j] = root_i;
+                }
+            }
+        }
+    }
+
+    let mut clusters: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for index in 0..file_hashes.len() {
+        let root = find_root(&mut parents, index);
+        clusters.entry(root).or_default().push(index);
+    }
+    clusters
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .collect()
+}
+
+/// Splits the hashes into `max_distance + 1` bands of bits and buckets the files by the value of
+/// each band. Two hashes that differ in at most `max_distance` bits match in at least one band, so
+/// only files of the same bucket need to be compared. The indices of each bucket stay ordered by
+/// date.
+fn candidate_buckets(file_hashes: &[FileHash], max_distance: u32) -> Vec<Vec<usize>> {
+    let band_count = (max_distance as usize + 1).min(64);
+    let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    for band in 0..band_count {
+        let start_bit = band * 64 / band_count;
+        let end_bit = (band + 1) * 64 / band_count;
+        let mask = u64::MAX >> (64 - (end_bit - start_bit));
+        for (index, file_hash) in file_hashes.iter().enumerate() {
+            let band_value = (file_hash.dhash as u64 >> start_bit) & mask;
+            buckets.entry((band, band_value)).or_default().push(index);
+        }
+    }
+    buckets
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn file_hash(file_id: i32, seconds: u32, dhash: i64) -> FileHash {
+        FileHash {
+            file_id,
+            rel_path: format!("image{}.jpg", file_id),
+            file_type: "IMAGE".to_string(),
+            effective_date: NaiveDate::from_ymd(2022, 2, 26).and_hms(12, 0, seconds),
+            dhash,
+        }
+    }
+
+    #[test]
+    fn hamming_distance_counts_different_bits() {
+        assert_eq!(0, hamming_distance(0b1011, 0b1011));
+        assert_eq!(2, hamming_distance(0b1011, 0b0001));
+        assert_eq!(64, hamming_distance(0, -1));
+    }
+
+    #[test]
+    fn no_clusters_without_similar_files() {
+        let file_hashes = vec![file_hash(1, 0, 0), file_hash(2, 1, -1)];
+        assert_eq!(0, cluster_indices(&file_hashes, 10, None).len());
+    }
+
+    #[test]
+    fn similar_files_are_clustered_transitively() {
+        let file_hashes = vec![
+            file_hash(1, 0, 0b0000),
+            file_hash(2, 1, -1),
+            file_hash(3, 2, 0b0011),
+            file_hash(4, 3, 0b1111),
+        ];
+        let clusters = cluster_indices(&file_hashes, 2, None);
+        assert_eq!(vec![vec![0, 2, 3]], clusters);
+    }
+
+    #[test]
+    fn bursts_only_cluster_files_taken_close_together() {
+        let file_hashes = vec![
+            file_hash(1, 0, 0b0000),
+            file_hash(2, 1, 0b0001),
+            file_hash(3, 30, 0b0001),
+        ];
+        assert_eq!(vec![vec![0, 1, 2]], cluster_indices(&file_hashes, 2, None));
+        assert_eq!(vec![vec![0, 1]], cluster_indices(&file_hashes, 2, Some(5)));
+    }
+
+    #[test]
+    fn similar_files_share_a_candidate_bucket() {
+        let file_hashes = vec![
+            file_hash(1, 0, 0x0000_0000_0000_0000),
+            file_hash(2, 1, 0x0000_0000_0000_0007),
+            file_hash(3, 2, 0x0101_0101_0101_0101),
+        ];
+        let buckets = candidate_buckets(&file_hashes, 3);
+        assert!(buckets.contains(&vec![0, 1]));
+        assert!(!buckets.iter().any(|bucket| bucket.contains(&2)));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::diesel::RunQueryDsl;
+use crate::queries::duplicates::UNIQUE_FILES_CONDITION;
 use crate::FotoboekDatabase;
-use diesel::sql_types::{Integer, Text};
+use diesel::sql_types::{Integer, Nullable, Text};
 use serde::Serialize;
 use std::collections::BTreeMap;
 
@@ -8,10 +9,16 @@ use std::collections::BTreeMap;
 pub struct TimelineFileInfo {
     id: i32,
     r#type: String,
+    /// Hash of the file contents, the version of its preview and video URLs
+    file_hash: String,
+    /// Duration of videos in seconds
+    video_duration: Option<i32>,
 }
 pub type TimelineDates = BTreeMap<String, Vec<TimelineFileInfo>>;
 
-pub async fn dates(db: FotoboekDatabase) -> TimelineDates {
+/// Returns the files by date. If `hide_duplicates` is set, only one file of files with identical
+/// contents is returned.
+pub async fn dates(db: FotoboekDatabase, hide_duplicates: bool) -> TimelineDates {
     #[derive(QueryableByName)]
     struct DateAndFileInfo {
         #[sql_type = "Text"]
@@ -20,18 +27,28 @@ pub async fn dates(db: FotoboekDatabase) -> TimelineDates {
         file_id: i32,
         #[sql_type = "Text"]
         file_type: String,
+        #[sql_type = "Text"]
+        file_hash: String,
+        #[sql_type = "Nullable<Integer>"]
+        video_duration: Option<i32>,
     }
 
     db.run(move |conn| {
-        let sql = r#"
+        let mut sql = r#"
             SELECT
                 DATE(file_metadata.effective_date) as date,
                 files.id AS file_id,
-                files.file_type AS file_type
+                files.file_type AS file_type,
+                file_metadata.file_hash AS file_hash,
+                file_metadata.video_duration AS video_duration
             FROM files
             INNER JOIN file_metadata
                 ON files.id = file_metadata.file_id
-       "#;
+       "#
+        .to_string();
+        if hide_duplicates {
+            sql = format!("{} WHERE {}", sql, UNIQUE_FILES_CONDITION);
+        }
 
         let image_dates: Vec<DateAndFileInfo> = diesel::sql_query(sql)
             .load(conn)
@@ -42,6 +59,8 @@ pub async fn dates(db: FotoboekDatabase) -> TimelineDates {
             entry.push(TimelineFileInfo {
                 id: it.file_id,
                 r#type: it.file_type.clone(),
+                file_hash: it.file_hash.clone(),
+                video_duration: it.video_duration,
             });
             return map;
         })
@@ -16,6 +16,13 @@ table! {
         exif_gps_lon -> Nullable<Float>,
         effective_date -> Timestamp,
         filename_date -> Nullable<Timestamp>,
+        video_duration -> Nullable<Integer>,
+        video_codec -> Nullable<Text>,
+        audio_codec -> Nullable<Text>,
+        video_frame_rate -> Nullable<Float>,
+        video_rotation -> Nullable<Integer>,
+        exif_orientation -> Nullable<Integer>,
+        file_modified_at -> Nullable<Timestamp>,
     }
 }
 
@@ -28,6 +35,34 @@ table! {
     }
 }
 
+table! {
+    perceptual_hashes (file_id) {
+        file_id -> Nullable<Integer>,
+        dhash -> BigInt,
+    }
+}
+
+table! {
+    scans (id) {
+        id -> Nullable<Integer>,
+        triggered_by -> Text,
+        status -> Text,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        images_total -> Integer,
+        images_added -> Integer,
+        images_removed -> Integer,
+        videos_total -> Integer,
+        videos_added -> Integer,
+        videos_removed -> Integer,
+        errors -> Nullable<Text>,
+        images_modified -> Integer,
+        videos_modified -> Integer,
+        images_moved -> Integer,
+        videos_moved -> Integer,
+    }
+}
+
 table! {
     tasks (id) {
         id -> Nullable<Integer>,
@@ -35,15 +70,23 @@ table! {
         module -> Text,
         priority -> Integer,
         work_started_at -> Timestamp,
-        max_worker_id -> Integer,
+        attempts -> Integer,
+        last_error -> Nullable<Text>,
+        next_attempt_at -> Timestamp,
+        failed_at -> Nullable<Timestamp>,
+        depends_on_module -> Nullable<Text>,
+        locked_by -> Nullable<Text>,
     }
 }
 
 joinable!(file_metadata -> files (file_id));
+joinable!(perceptual_hashes -> files (file_id));
 joinable!(tasks -> files (file_id));
 
 allow_tables_to_appear_in_same_query!(
     file_metadata,
     files,
+    perceptual_hashes,
+    scans,
     tasks,
 );
@@ -1,3 +1,4 @@
+use std::str::FromStr;
 use strum_macros::{EnumString, ToString};
 
 #[derive(Clone, Debug)]
@@ -5,14 +6,229 @@ pub struct FotoboekConfig {
     pub media_source_path: String,
     pub file_storage_path: String,
     pub webapp_files_path: String,
-    pub num_worker_threads: usize,
+    pub worker_pools: Vec<WorkerPool>,
+    pub worker_idle_interval_sec: usize,
     pub task_lock_timeout_sec: usize,
+    pub task_max_attempts: usize,
+    pub task_retry_backoff_sec: usize,
+    pub fs_watcher_enabled: bool,
+    pub scan_on_startup: bool,
+    pub scan_interval_hours: usize,
+    pub similarity_max_distance: u32,
+    pub image_extensions: Vec<String>,
+    pub raw_extensions: Vec<String>,
+    pub video_extensions: Vec<String>,
+    pub preview_sizes: Vec<PreviewSize>,
+    pub preview_on_demand_max_concurrent: usize,
 }
 
-#[derive(PartialEq, EnumString, ToString)]
-pub enum PreviewSize {
-    #[strum(serialize = "large")]
-    Large,
-    #[strum(serialize = "small")]
-    Small,
+impl FotoboekConfig {
+    pub fn num_workers(&self) -> usize {
+        self.worker_pools.iter().map(|pool| pool.workers).sum()
+    }
+
+    pub fn preview_size(&self, name: &str) -> Option<&PreviewSize> {
+        self.preview_sizes.iter().find(|size| size.name == name)
+    }
+}
+
+/// Name of the preview size used for thumbnails and perceptual hashes, which must be configured.
+pub const PREVIEW_SIZE_SMALL: &str = "small";
+/// Name of the preview size used to present a single image, which must be configured.
+pub const PREVIEW_SIZE_LARGE: &str = "large";
+
+/// A pool of workers that run the tasks of the given modules, configured as
+/// `name:workers:module+module`, e.g. `preview:2:preview+phash`. Pools cap the concurrency of their
+/// modules independently, so tasks of one module never starve those of another pool.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerPool {
+    pub name: String,
+    pub workers: usize,
+    pub modules: Vec<String>,
+}
+
+/// A preview rendition, configured as `name:fit:pixels:format:quality`, e.g.
+/// `large:contain:2000:webp:85`. Previews are never larger than the original image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PreviewSize {
+    pub name: String,
+    pub fit: PreviewFit,
+    pub pixels: i32,
+    pub format: PreviewFormat,
+    pub quality: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, EnumString, ToString)]
+pub enum PreviewFit {
+    /// The shorter edge of the preview is scaled to the number of pixels, so the preview covers a
+    /// square of that size, e.g. for thumbnails.
+    #[strum(serialize = "cover")]
+    Cover,
+    /// The longer edge of the preview is scaled to the number of pixels, so the preview fits into a
+    /// square of that size.
+    #[strum(serialize = "contain")]
+    Contain,
+}
+
+#[derive(Clone, Debug, PartialEq, EnumString, ToString)]
+pub enum PreviewFormat {
+    #[strum(serialize = "webp")]
+    Webp,
+    #[strum(serialize = "avif")]
+    Avif,
+    #[strum(serialize = "jpeg")]
+    Jpeg,
+}
+
+impl PreviewFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PreviewFormat::Webp => "webp",
+            PreviewFormat::Avif => "avif",
+            PreviewFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            PreviewFormat::Webp => "image/webp",
+            PreviewFormat::Avif => "image/avif",
+            PreviewFormat::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+impl FromStr for PreviewSize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid preview size: {}", value);
+
+        let parts: Vec<&str> = value.split(':').map(|part| part.trim()).collect();
+        let (name, fit, pixels, format, quality) = match parts[..] {
+            [name, fit, pixels, format, quality] => (name, fit, pixels, format, quality),
+            _ => return Err(invalid()),
+        };
+        // The name ends up in file names and URLs
+        let is_valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !is_valid_name {
+            return Err(invalid());
+        }
+
+        Ok(PreviewSize {
+            name: name.to_string(),
+            fit: fit.parse().map_err(|_| invalid())?,
+            pixels: pixels
+                .parse()
+                .ok()
+                .filter(|pixels| *pixels > 0)
+                .ok_or_else(invalid)?,
+            format: format.parse().map_err(|_| invalid())?,
+            quality: quality
+                .parse()
+                .ok()
+                .filter(|quality| (1..=100).contains(quality))
+                .ok_or_else(invalid)?,
+        })
+    }
+}
+
+impl FromStr for WorkerPool {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid worker pool: {}", value);
+
+        let parts: Vec<&str> = value.split(':').map(|part| part.trim()).collect();
+        let (name, workers, modules) = match parts[..] {
+            [name, workers, modules] => (name, workers, modules),
+            _ => return Err(invalid()),
+        };
+        // The name ends up in the lock owner of tasks and in logs
+        let is_valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        let modules: Vec<String> = modules
+            .split('+')
+            .map(|module| module.trim().to_string())
+            .collect();
+        if !is_valid_name || modules.iter().any(|module| module.is_empty()) {
+            return Err(invalid());
+        }
+
+        Ok(WorkerPool {
+            name: name.to_string(),
+            workers: workers
+                .parse()
+                .ok()
+                .filter(|workers| *workers > 0)
+                .ok_or_else(invalid)?,
+            modules,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_size_parsed() {
+        assert_eq!(
+            Ok(PreviewSize {
+                name: "uhd".to_string(),
+                fit: PreviewFit::Contain,
+                pixels: 3840,
+                format: PreviewFormat::Avif,
+                quality: 60,
+            }),
+            "uhd:contain:3840:avif:60".parse()
+        );
+        assert_eq!(
+            Ok(PreviewSize {
+                name: "small".to_string(),
+                fit: PreviewFit::Cover,
+                pixels: 200,
+                format: PreviewFormat::Webp,
+                quality: 85,
+            }),
+            " small : cover : 200 : webp : 85 ".parse()
+        );
+    }
+
+    #[test]
+    fn invalid_preview_size_rejected() {
+        assert!("small:cover:200:webp".parse::<PreviewSize>().is_err());
+        assert!("small:fill:200:webp:85".parse::<PreviewSize>().is_err());
+        assert!("small:cover:0:webp:85".parse::<PreviewSize>().is_err());
+        assert!("small:cover:200:png:85".parse::<PreviewSize>().is_err());
+        assert!("small:cover:200:webp:101".parse::<PreviewSize>().is_err());
+        assert!("../small:cover:200:webp:85".parse::<PreviewSize>().is_err());
+        assert!(":cover:200:webp:85".parse::<PreviewSize>().is_err());
+    }
+
+    #[test]
+    fn worker_pool_parsed() {
+        assert_eq!(
+            Ok(WorkerPool {
+                name: "preview".to_string(),
+                workers: 2,
+                modules: vec!["preview".to_string(), "phash".to_string()],
+            }),
+            " preview : 2 : preview + phash ".parse()
+        );
+    }
+
+    #[test]
+    fn invalid_worker_pool_rejected() {
+        assert!("preview:2".parse::<WorkerPool>().is_err());
+        assert!("preview:0:preview".parse::<WorkerPool>().is_err());
+        assert!("preview:2:".parse::<WorkerPool>().is_err());
+        assert!("preview:2:preview+".parse::<WorkerPool>().is_err());
+        assert!("pre-view:2:preview".parse::<WorkerPool>().is_err());
+    }
 }
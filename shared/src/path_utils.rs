@@ -17,21 +17,57 @@ pub fn rel_to_abs(config: &FotoboekConfig, rel_path: &str) -> String {
     abs_path.to_str().unwrap().to_string()
 }
 
+/// Resolves the relative path like `rel_to_abs`, but returns None if the file does not exist or is
+/// not located within the media source path once `..` components and symlinks are resolved.
+pub fn rel_to_abs_checked(config: &FotoboekConfig, rel_path: &str) -> Option<PathBuf> {
+    let media_source_path = Path::new(&config.media_source_path).canonicalize().ok()?;
+    let abs_path = media_source_path.join(rel_path).canonicalize().ok()?;
+    if abs_path.starts_with(&media_source_path) {
+        Some(abs_path)
+    } else {
+        None
+    }
+}
+
 pub fn get_filename(path: &PathBuf) -> String {
     path.file_name().unwrap().to_str().unwrap().to_string()
 }
 
+/// Returns true if the path has one of the given (lowercase) extensions, ignoring case.
+pub fn has_extension<S: AsRef<str>>(path: &Path, extensions: &[S]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| {
+            let extension = extension.to_lowercase();
+            extensions.iter().any(|it| it.as_ref() == extension)
+        })
+        .unwrap_or(false)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::path_utils::FotoboekConfig;
+    use std::path::Path;
 
     pub fn make_config() -> FotoboekConfig {
         FotoboekConfig {
             media_source_path: "/mnt/images".to_string(),
             file_storage_path: "".to_string(),
             webapp_files_path: "".to_string(),
-            num_worker_threads: 1,
+            worker_pools: vec![],
+            worker_idle_interval_sec: 60,
             task_lock_timeout_sec: 1,
+            task_max_attempts: 5,
+            task_retry_backoff_sec: 60,
+            fs_watcher_enabled: false,
+            scan_on_startup: false,
+            scan_interval_hours: 0,
+            similarity_max_distance: 0,
+            image_extensions: vec![],
+            raw_extensions: vec![],
+            video_extensions: vec![],
+            preview_sizes: vec![],
+            preview_on_demand_max_concurrent: 0,
         }
     }
 
@@ -60,4 +96,46 @@ mod tests {
             "/mnt/images/image.jpg"
         );
     }
+
+    #[test]
+    fn rel_to_abs_checked() {
+        let temp_dir = tempdir::TempDir::new("path_utils_unittest").unwrap();
+        let media_source_path = temp_dir.path().join("images");
+        std::fs::create_dir(&media_source_path).unwrap();
+        std::fs::File::create(media_source_path.join("image.jpg")).unwrap();
+        std::fs::File::create(temp_dir.path().join("secret.txt")).unwrap();
+
+        let mut config = make_config();
+        config.media_source_path = media_source_path.to_str().unwrap().to_string();
+
+        assert_eq!(
+            Some(media_source_path.canonicalize().unwrap().join("image.jpg")),
+            super::rel_to_abs_checked(&config, "image.jpg")
+        );
+        assert_eq!(None, super::rel_to_abs_checked(&config, "missing.jpg"));
+        assert_eq!(None, super::rel_to_abs_checked(&config, "../secret.txt"));
+        let abs_secret_path = temp_dir.path().join("secret.txt");
+        assert_eq!(
+            None,
+            super::rel_to_abs_checked(&config, abs_secret_path.to_str().unwrap())
+        );
+    }
+
+    #[test]
+    fn has_extension() {
+        let extensions = ["jpg", "jpeg"];
+        assert!(super::has_extension(
+            Path::new("/mnt/images/image.jpg"),
+            &extensions
+        ));
+        assert!(super::has_extension(
+            Path::new("dir/image.JPEG"),
+            &extensions
+        ));
+        assert!(!super::has_extension(
+            Path::new("dir/video.mp4"),
+            &extensions
+        ));
+        assert!(!super::has_extension(Path::new("dir/jpg"), &extensions));
+    }
 }
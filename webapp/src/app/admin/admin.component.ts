@@ -1,12 +1,19 @@
 import { Component, OnInit } from '@angular/core';
 import { EChartsOption } from "echarts";
 import { HttpClient } from "@angular/common/http";
-import { share } from "rxjs/operators";
-import { Observable } from "rxjs";
+import { filter, map, share, switchMap, takeWhile } from "rxjs/operators";
+import { Observable, timer } from "rxjs";
 
 declare var M: any;
 
-type Task = Object;
+interface Task {
+  id: number;
+  file_id: number;
+  module: string;
+  attempts: number;
+  last_error: string | null;
+  failed_at: string | null;
+}
 
 interface MediaDayStatistic {
   images_count: number;
@@ -18,12 +25,18 @@ interface MediaDayStatistic {
 type MediaStatistics = { [date: string]: MediaDayStatistic };
 
 interface ScanResult {
+  id: number,
+  status: string,
   images_total: number,
   images_added: number,
   images_removed: number,
+  images_modified: number,
+  images_moved: number,
   videos_total: number,
   videos_added: number,
   videos_removed: number,
+  videos_modified: number,
+  videos_moved: number,
 }
 
 @Component({
@@ -36,7 +49,8 @@ export class AdminComponent implements OnInit {
   private echartInstance: any;
 
   chartOption: EChartsOption = {};
-  tasks$: Observable<Task>;
+  openTasksCount$: Observable<number>;
+  failedTasks: Task[] = [];
   total_images_count: number;
   total_videos_count: number;
   total_files_size: number;
@@ -48,7 +62,11 @@ export class AdminComponent implements OnInit {
   }
 
   ngOnInit(): void {
-    this.tasks$ = this.http.get('/api/admin/tasks').pipe(share());
+    this.openTasksCount$ = this.http.get<Task[]>('/api/admin/tasks').pipe(
+      map(tasks => tasks.filter(task => task.failed_at == null).length),
+      share(),
+    );
+    this.loadFailedTasks();
 
     const mediaStatistic$ = this.http.get('/api/admin/media-statistics') as Observable<MediaStatistics>;
     mediaStatistic$
@@ -128,6 +146,27 @@ export class AdminComponent implements OnInit {
     const [modal_instance] = M.Modal.init(document.querySelectorAll('#scan-modal'), options);
     modal_instance.open();
 
-    this.http.post('/api/admin/scan', {}).subscribe((result: ScanResult) => this.scan_result = result);
+    this.http.post<ScanResult>('/api/admin/scan', {}).pipe(
+      switchMap(scan => timer(0, 1000).pipe(
+        switchMap(() => this.http.get<ScanResult>(`/api/admin/scans/${scan.id}`)),
+      )),
+      takeWhile(scan => scan.status === 'RUNNING', true),
+      filter(scan => scan.status !== 'RUNNING'),
+    ).subscribe(result => this.scan_result = result);
+  }
+
+  private loadFailedTasks() {
+    this.http.get<Task[]>('/api/admin/tasks/failed')
+      .subscribe(tasks => this.failedTasks = tasks);
+  }
+
+  retryTask(task: Task) {
+    this.http.post(`/api/admin/tasks/${task.id}/retry`, {})
+      .subscribe(() => this.loadFailedTasks());
+  }
+
+  discardTask(task: Task) {
+    this.http.delete(`/api/admin/tasks/${task.id}`)
+      .subscribe(() => this.loadFailedTasks());
   }
 }
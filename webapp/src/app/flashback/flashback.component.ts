@@ -4,7 +4,7 @@ import { MediaPresenterService } from '../media-presenter/media-presenter.servic
 
 type FlashbackDates = string[];
 type FlashbackFiles = { [date: string]: FlashbackFile[] };
-export type FlashbackFile = { id: number, type: 'IMAGE' | 'VIDEO' };
+export type FlashbackFile = { id: number, type: 'IMAGE' | 'VIDEO', file_hash: string, video_duration: number | null };
 
 @Component({
   selector: 'app-flashback',
@@ -35,7 +35,7 @@ export class FlashbackComponent implements OnInit {
   onImageClick(date: string, file: FlashbackFile) {
     const files = this.flashbackFiles[date];
     const startIndex = files.indexOf(file);
-    const items = files.map(file => this.mediaPresenterService.mapToGalleryItem(file.type, file.id));
+    const items = files.map(file => this.mediaPresenterService.mapToGalleryItem(file.type, file.id, file.file_hash));
     this.mediaPresenterService.startPresentation(items, startIndex);
   }
 }
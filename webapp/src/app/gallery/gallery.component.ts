@@ -14,7 +14,10 @@ export interface GalleryFile {
   id: number;
   file_name: string;
   file_type: 'IMAGE' | 'VIDEO';
+  file_hash: string;
   effective_date: string;
+  raw_file_id: number | null;
+  video_duration: number | null;
 }
 
 @Component({
@@ -46,18 +49,17 @@ export class GalleryComponent implements OnInit {
     });
   }
 
-  get_preview_images_for_sub_path(gallery_path: GalleryPath): number[] {
+  get_preview_files_for_sub_path(gallery_path: GalleryPath): GalleryFile[] {
     if (gallery_path.files.length <= 4) {
-      return gallery_path.files.map((file) => file.id);
+      return gallery_path.files;
     }
 
-    const imageIds = [];
+    const previewFiles = [];
     for (let i = 0; i < 4; i++) {
       const fileIndex = Math.floor((gallery_path.files.length / 4) * +i);
-      const imageId = gallery_path.files[fileIndex].id;
-      imageIds.push(imageId);
+      previewFiles.push(gallery_path.files[fileIndex]);
     }
-    return imageIds;
+    return previewFiles;
   }
 
   breadcrumbs(): string[] {
@@ -138,7 +140,7 @@ export class GalleryComponent implements OnInit {
 
     const startIndex = files.indexOf(file);
     const items = files.map((file: GalleryFile) => this.mediaPresenterService.mapToGalleryItem(
-      file.file_type, file.id
+      file.file_type, file.id, file.file_hash
     ));
 
     this.mediaPresenterService.startPresentation(items, startIndex);
@@ -17,12 +17,13 @@ export class MediaPresenterService {
 
   mapToGalleryItem(
     file_type: 'IMAGE' | 'VIDEO',
-    file_id: number
+    file_id: number,
+    file_hash: string
   ): GalleryItem {
     if (file_type === 'IMAGE') {
       return {
-        src: `/api/images/${file_id}?size=large`,
-        thumb: `/api/images/${file_id}?size=small`,
+        src: `/api/images/${file_id}?size=large&v=${file_hash}`,
+        thumb: `/api/images/${file_id}?size=small&v=${file_hash}`,
       };
     } else if (file_type === 'VIDEO') {
       const attributes: HTMLVideoElement = {
@@ -32,7 +33,7 @@ export class MediaPresenterService {
       return {
         video: {
           source: [
-            { src: `/api/videos/${file_id}`, type: 'video/webm' }
+            { src: `/api/videos/${file_id}?v=${file_hash}`, type: 'video/webm' }
           ],
           tracks: [],
           attributes,
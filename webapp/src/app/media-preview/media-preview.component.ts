@@ -1,5 +1,8 @@
 import { Component, Input, OnInit } from '@angular/core';
 
+// Number of frames in the sprite sheet of a video, see /api/videos/<id>/sprite
+const SPRITE_FRAMES = 10;
+
 @Component({
   selector: 'app-media-preview',
   templateUrl: './media-preview.component.html',
@@ -13,9 +16,58 @@ export class MediaPreviewComponent implements OnInit {
   @Input()
   file_type: 'IMAGE' | 'VIDEO';
 
+  // Version of the preview URLs, lets browsers cache them for good
+  @Input()
+  file_hash: string;
+
+  @Input()
+  video_duration: number | null = null;
+
+  // Frame of the sprite sheet shown while the mouse moves over a video preview
+  scrubFrame: number | null = null;
+
   constructor() { }
 
   ngOnInit(): void {
   }
 
+  backgroundImage(): string {
+    const preview = `url(/api/images/${this.file_id}?size=small&v=${this.file_hash})`;
+    if (this.scrubFrame === null) {
+      return preview;
+    }
+    // The preview stays visible below the sprite sheet until it is loaded
+    return `url(/api/videos/${this.file_id}/sprite?v=${this.file_hash}), ${preview}`;
+  }
+
+  backgroundSize(): string | null {
+    return this.scrubFrame === null ? null : `${SPRITE_FRAMES * 100}% 100%, cover`;
+  }
+
+  backgroundPosition(): string | null {
+    if (this.scrubFrame === null) {
+      return null;
+    }
+    return `${this.scrubFrame / (SPRITE_FRAMES - 1) * 100}% 0, center`;
+  }
+
+  onMouseMove(event: MouseEvent): void {
+    if (this.file_type !== 'VIDEO') {
+      return;
+    }
+    const rect = (event.currentTarget as HTMLElement).getBoundingClientRect();
+    const fraction = (event.clientX - rect.left) / rect.width;
+    this.scrubFrame = Math.max(0, Math.min(SPRITE_FRAMES - 1, Math.floor(fraction * SPRITE_FRAMES)));
+  }
+
+  onMouseLeave(): void {
+    this.scrubFrame = null;
+  }
+
+  formattedDuration(): string {
+    const minutes = Math.floor(this.video_duration / 60);
+    const seconds = this.video_duration % 60;
+    return `${minutes}:${seconds.toString().padStart(2, '0')}`;
+  }
+
 }
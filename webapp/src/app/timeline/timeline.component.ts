@@ -5,7 +5,7 @@ import { MediaPresenterService } from '../media-presenter/media-presenter.servic
 
 export type TimelineDates = string[];
 export type TimelineFiles = { [date: string]: TimelineFile[] };
-export type TimelineFile = { id: number, type: 'IMAGE' | 'VIDEO' };
+export type TimelineFile = { id: number, type: 'IMAGE' | 'VIDEO', file_hash: string, video_duration: number | null };
 
 declare var M: any;
 
@@ -79,7 +79,7 @@ export class TimelineComponent implements OnInit {
     }, [] as TimelineFile[]);
 
     const items = files.map(file => this.mediaPresenterService.mapToGalleryItem(
-      file.type, file.id
+      file.type, file.id, file.file_hash
     ));
     const startIndex = files.indexOf(file);
     this.mediaPresenterService.startPresentation(items, startIndex);